@@ -0,0 +1,1360 @@
+use poem::{
+    http::StatusCode, session::Session, web::Data, Body, EndpointExt, Error, FromRequest,
+    IntoEndpoint, IntoResponse, Request, RequestBody, Response, Route,
+};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::{Binary, Json},
+    ApiResponse, Enum, Object, OpenApi, OpenApiService, Tags,
+};
+use rand::Rng;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::db::{unix_time_now, KeyId, KeyInfo, SharedStorage, UserId};
+use super::eip712::TypedData;
+use super::keyring::{Keyring, SealedKey};
+use super::keystore::{Kdf, KeyExportPolicy};
+use super::password::PasswordHashing;
+use super::psbt::Psbt;
+use super::registration::{register_user, RegistrationMode};
+use super::service::{
+    check_derivation_path, check_message, check_sign_options, decode_hex_or_base64, digest_reader,
+    BitcoinAddressType, DerivedAddress, HashFunction, MessageEncoding, MessageHash, PublicKeyInfo,
+    SignOptions, SignService, SignServiceError, SignatureBundle, SignatureEncoding,
+    SignatureScheme,
+};
+use super::token::{BearerAuth, Scope, TokenAuth};
+use super::web_app::{
+    add_hd_user_key, authenticate, derive_addresses, derive_public_key, export_user_key,
+    generate_user_key, import_user_key, KeyExport, KeyImport, WebApp,
+};
+
+/// Finished jobs are forgotten after this many seconds.
+const JOB_RETENTION_SECS: u64 = 3600;
+
+#[derive(Tags)]
+enum ApiTags {
+    /// Session handling
+    Auth,
+    /// Key management
+    Keys,
+    /// Message signing and verification
+    Signing,
+}
+
+fn message_hash(function: HashFunction, prehashed: bool, encoding: MessageEncoding) -> MessageHash {
+    MessageHash {
+        function,
+        prehashed,
+        encoding,
+    }
+}
+
+#[derive(Clone, Copy, Enum, PartialEq)]
+#[oai(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Message signing started by `POST /api/v1/sign`, signing runs in the background.
+#[derive(Clone, Object)]
+pub struct SignJob {
+    job_id: String,
+    #[oai(skip)]
+    user_id: UserId,
+    key_id: KeyId,
+    scheme: SignatureScheme,
+    #[oai(skip_serializing_if_is_none)]
+    encoding: Option<SignatureEncoding>,
+    /// Hash function of the message, for the schemes which let it be chosen.
+    #[oai(skip_serializing_if_is_none)]
+    hash: Option<HashFunction>,
+    /// Whether the message was the digest.
+    prehashed: bool,
+    message_encoding: MessageEncoding,
+    status: JobStatus,
+    /// Signature encoded as the scheme specifies, set once the job is done.
+    #[oai(skip_serializing_if_is_none)]
+    signature: Option<String>,
+    /// The 32 byte digest which was signed in 0x-hex, set once the job is
+    /// done. Not given for `psbt`, each input has its own.
+    #[oai(skip_serializing_if_is_none)]
+    digest: Option<String>,
+    /// Signature bundle for a `.sig.json` file, set once the job is done.
+    #[oai(skip_serializing_if_is_none)]
+    bundle: Option<SignatureBundle>,
+    /// Reason of the failure, set once the job failed.
+    #[oai(skip_serializing_if_is_none)]
+    error: Option<String>,
+    /// Seconds since the Unix epoch.
+    created_at: u64,
+}
+
+#[derive(Object)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Object)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Object)]
+struct RegisterRequest {
+    /// 3 to 32 letters, digits, `-`, `_` or `.`, starting with a letter or digit.
+    username: String,
+    /// 8 to 128 characters.
+    password: String,
+    /// Required when registration is limited to invited users.
+    invite_code: Option<String>,
+}
+
+#[derive(Object)]
+struct UserResponse {
+    user_id: UserId,
+    username: String,
+}
+
+#[derive(Object)]
+struct GenerateKeyRequest {
+    /// 1 to 32 letters, digits, spaces, `-`, `_` or `.`, unique per user.
+    label: String,
+    /// Non-exportable keys never leave waas, defaults to `true`.
+    #[oai(default = "default_exportable")]
+    exportable: bool,
+}
+
+fn default_exportable() -> bool {
+    true
+}
+
+#[derive(Object)]
+struct ImportKeyRequest {
+    /// 1 to 32 letters, digits, spaces, `-`, `_` or `.`, unique per user.
+    label: String,
+    /// secp256k1 private key as 32 bytes in hex, Bitcoin WIF, SEC1
+    /// `EC PRIVATE KEY` PEM, PKCS#8 `PRIVATE KEY` or `ENCRYPTED PRIVATE KEY` PEM,
+    /// or an Ethereum keystore v3 file.
+    key: String,
+    /// Password of an `ENCRYPTED PRIVATE KEY` or a keystore file.
+    password: Option<String>,
+    /// Non-exportable keys never leave waas, defaults to `true`.
+    #[oai(default = "default_exportable")]
+    exportable: bool,
+}
+
+#[derive(Object)]
+struct ExportKeyRequest {
+    /// Password of the account, export needs it again.
+    password: String,
+    /// Password the keystore file is encrypted under, 8 to 128 characters.
+    keystore_password: String,
+    /// Defaults to `scrypt`.
+    #[oai(default)]
+    kdf: Kdf,
+}
+
+#[derive(Object)]
+struct AddHdKeyRequest {
+    /// 1 to 32 letters, digits, spaces, `-`, `_` or `.`, unique per user.
+    label: String,
+    /// English BIP-39 mnemonic to import, a new 24 word mnemonic is
+    /// generated if missing.
+    mnemonic: Option<String>,
+    /// Optional BIP-39 passphrase.
+    #[oai(default)]
+    passphrase: String,
+}
+
+#[derive(Object)]
+struct KeyResponse {
+    key_id: KeyId,
+    /// Id of the public key served to anyone at `/keys/{public_id}/public`.
+    public_id: String,
+    label: String,
+    algorithm: String,
+    /// Seconds since the Unix epoch.
+    created_at: u64,
+    /// Whether the key can be exported to a keystore file.
+    exportable: bool,
+    /// Missing when the key can't be opened. The master key of HD keys.
+    public_key: Option<PublicKeyInfo>,
+    /// First addresses of the standard accounts of HD keys.
+    #[oai(skip_serializing_if_is_empty)]
+    derived_addresses: Vec<DerivedAddress>,
+}
+
+#[derive(Object)]
+struct HdKeyResponse {
+    #[oai(flatten)]
+    key: KeyResponse,
+    /// The generated mnemonic, it is not stored and only returned once.
+    #[oai(skip_serializing_if_is_none)]
+    mnemonic: Option<String>,
+}
+
+#[derive(Object)]
+struct SignRequest {
+    key_id: KeyId,
+    /// BIP-32 path of the child key of an HD key, e.g. `m/44'/60'/0'/0/0`,
+    /// HD keys sign with their master key without one.
+    derivation_path: Option<String>,
+    /// Defaults to `ecdsa`.
+    #[oai(default)]
+    scheme: SignatureScheme,
+    /// `hex`, `base64` or `base64url` for schemes with a fixed-size
+    /// signature, also `der` and `jws` for `ecdsa`. The encoding of the
+    /// scheme without one.
+    encoding: Option<SignatureEncoding>,
+    /// Defaults to `sha256`, only `ecdsa`, `ecdsa-recoverable` and `bip340`
+    /// let it be chosen.
+    #[oai(default)]
+    hash: HashFunction,
+    /// Defaults to `p2pkh`, only `bip137` lets it be chosen.
+    #[oai(default)]
+    address_type: BitcoinAddressType,
+    /// The message is the 32 byte digest, already computed with `hash`. In
+    /// hex for text messages.
+    #[oai(default)]
+    prehashed: bool,
+    /// Defaults to `text`, binary messages are given in `hex` or `base64`
+    /// and need `ecdsa`, `ecdsa-recoverable` or `bip340`.
+    #[oai(default)]
+    message_encoding: MessageEncoding,
+    message: String,
+}
+
+impl SignRequest {
+    /// Request of the structured signing endpoints, the payload is the
+    /// message of a scheme with its own hash and encoding.
+    fn payload(
+        key_id: KeyId,
+        derivation_path: Option<String>,
+        scheme: SignatureScheme,
+        message: String,
+    ) -> Json<Self> {
+        Json(SignRequest {
+            key_id,
+            derivation_path,
+            scheme,
+            encoding: None,
+            hash: HashFunction::default(),
+            address_type: BitcoinAddressType::default(),
+            prehashed: false,
+            message_encoding: MessageEncoding::Text,
+            message,
+        })
+    }
+}
+
+#[derive(Object)]
+struct SignTypedDataRequest {
+    key_id: KeyId,
+    /// BIP-32 path of the child key of an HD key.
+    derivation_path: Option<String>,
+    /// EIP-712 typed data with `types`, `primaryType`, `domain` and `message`.
+    typed_data: serde_json::Value,
+}
+
+#[derive(Object)]
+struct SignTransactionRequest {
+    key_id: KeyId,
+    /// BIP-32 path of the child key of an HD key.
+    derivation_path: Option<String>,
+    /// Transaction fields as for `eth_sendTransaction`: `chainId`, `nonce`,
+    /// `gas`, `to`, `value`, `data`, `accessList` and `gasPrice` or
+    /// `maxFeePerGas` with `maxPriorityFeePerGas`. The type is inferred
+    /// from the fees unless `type` is given. An optional `from` must be the
+    /// address of the key.
+    transaction: serde_json::Value,
+}
+
+#[derive(Object)]
+struct SignPsbtRequest {
+    key_id: KeyId,
+    /// BIP-32 path of the child key of an HD key.
+    derivation_path: Option<String>,
+    /// Base64 encoded PSBT, P2WPKH and P2PKH inputs of the key are signed.
+    psbt: String,
+}
+
+#[derive(Object)]
+struct SignNostrEventRequest {
+    key_id: KeyId,
+    /// BIP-32 path of the child key of an HD key.
+    derivation_path: Option<String>,
+    /// Unsigned event with `created_at`, `kind`, `tags` and `content`.
+    /// `pubkey` is filled in from the key if missing.
+    event: serde_json::Value,
+}
+
+#[derive(Object)]
+struct DecodePsbtRequest {
+    /// Base64 encoded PSBT.
+    psbt: String,
+    /// SEC1 public key, hex or base64 encoded, to check which inputs it signs.
+    public_key: Option<String>,
+}
+
+#[derive(Object)]
+struct PsbtInput {
+    /// Spent outpoint as `txid:vout`.
+    outpoint: String,
+    /// Address of the spent output, unknown if the PSBT doesn't include it.
+    address: Option<String>,
+    /// Amount of the spent output in satoshis.
+    value: Option<u64>,
+    /// Whether the amount comes from the previous transaction, inputs of
+    /// the key can only be signed if it does.
+    value_verified: bool,
+    /// Whether the input is signed by the public key.
+    signable: bool,
+}
+
+#[derive(Object)]
+struct PsbtOutput {
+    /// Address or `script <hex>` for non-standard scripts.
+    address: String,
+    /// Amount in satoshis.
+    value: u64,
+}
+
+#[derive(Object)]
+struct DecodePsbtResponse {
+    inputs: Vec<PsbtInput>,
+    outputs: Vec<PsbtOutput>,
+    /// Fee in satoshis, unknown if an input amount is missing.
+    fee: Option<u64>,
+    /// Whether all input amounts come from their previous transactions,
+    /// otherwise the fee can't be trusted.
+    fee_verified: bool,
+}
+
+#[derive(Object)]
+struct TypedDataHashRequest {
+    typed_data: serde_json::Value,
+}
+
+/// Hashes of EIP-712 typed data, 0x-hex encoded.
+#[derive(Object)]
+struct TypedDataHashResponse {
+    domain_separator: String,
+    /// `hashStruct` of the message.
+    struct_hash: String,
+    /// Digest which is signed.
+    digest: String,
+}
+
+#[derive(Object)]
+struct VerifyRequest {
+    /// Defaults to `ecdsa`.
+    #[oai(default)]
+    scheme: SignatureScheme,
+    /// Without it the digest of the bundle is verified, for `ecdsa`,
+    /// `ecdsa-recoverable` and `bip340` signatures which are not a JWS.
+    message: Option<String>,
+    /// Hex, base64 or base64url encoded and low-S for ECDSA. 64 byte r||s,
+    /// DER or a JWS, which may have a detached payload, for `ecdsa`, 65 byte
+    /// r||s||recid for `ecdsa-recoverable`, 64 byte r||s
+    /// for `bip340`, 65 byte r||s||v for `eip191` and `eip712`, 65 byte
+    /// header||r||s for `bip137`, the raw signed transaction for `eth-tx`,
+    /// the signed PSBT for `psbt`, the `sig` or the signed event for `nostr`.
+    /// Not needed with a bundle.
+    signature: Option<String>,
+    /// SEC1 public key, hex or base64 encoded. For `bip340` and `nostr` also
+    /// x-only or npub, for
+    /// `eip191`, `eip712` and `eth-tx` also an Ethereum address, for `bip137`
+    /// and `psbt` a Bitcoin address, for `ecdsa-recoverable` either address.
+    /// Not needed with a bundle.
+    public_key: Option<String>,
+    /// Hash function of the message as for signing.
+    #[oai(default)]
+    hash: HashFunction,
+    /// The message is the digest as for signing.
+    #[oai(default)]
+    prehashed: bool,
+    /// How the message is given as for signing.
+    #[oai(default)]
+    message_encoding: MessageEncoding,
+    /// Signature bundle of a signing job or `.sig.json` file, its scheme,
+    /// hash, signature and public key are verified.
+    bundle: Option<SignatureBundle>,
+}
+
+#[derive(Object)]
+struct RecoverRequest {
+    /// One of `ecdsa-recoverable`, `eip191`, `eip712`, `eth-tx` and `bip137`.
+    scheme: SignatureScheme,
+    message: String,
+    /// Hex or base64 encoded like for verification.
+    signature: String,
+    /// Hash function of the message as for signing.
+    #[oai(default)]
+    hash: HashFunction,
+    /// The message is the digest as for signing.
+    #[oai(default)]
+    prehashed: bool,
+    /// How the message is given as for signing.
+    #[oai(default)]
+    message_encoding: MessageEncoding,
+}
+
+#[derive(Object)]
+struct VerifyResponse {
+    valid: bool,
+    /// Why the signature is not valid.
+    #[oai(skip_serializing_if_is_none)]
+    reason: Option<String>,
+}
+
+/// Key with its public key and, for HD keys, its derived addresses.
+async fn key_response(
+    user_id: UserId,
+    key: KeyInfo,
+    sealed_key: &SealedKey,
+    keyring: &Keyring,
+    sign_service: &Mutex<SignService>,
+) -> KeyResponse {
+    let public_key =
+        derive_public_key(user_id, &key, sealed_key, None, keyring, sign_service).await;
+    let derived_addresses =
+        derive_addresses(user_id, &key, sealed_key, keyring, sign_service).await;
+    KeyResponse {
+        key_id: key.id,
+        public_id: key.public_id,
+        label: key.label,
+        algorithm: key.algorithm,
+        created_at: key.created_at,
+        exportable: key.exportable,
+        public_key,
+        derived_addresses,
+    }
+}
+
+#[derive(ApiResponse)]
+enum NoContent {
+    #[oai(status = 204)]
+    NoContent,
+}
+
+#[derive(ApiResponse)]
+enum UserCreated {
+    /// The account was created, log in to use it.
+    #[oai(status = 201)]
+    Created(Json<UserResponse>),
+}
+
+#[derive(ApiResponse)]
+enum KeyCreated {
+    /// The key was generated.
+    #[oai(status = 201)]
+    Created(Json<KeyResponse>),
+}
+
+#[derive(ApiResponse)]
+enum HdKeyCreated {
+    /// The HD key was generated or imported.
+    #[oai(status = 201)]
+    Created(Json<HdKeyResponse>),
+}
+
+#[derive(ApiResponse)]
+enum SignAccepted {
+    /// Signing was started, poll the job for the signature.
+    #[oai(status = 202)]
+    Accepted(Json<SignJob>),
+}
+
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "bad_request")]
+enum ApiError {
+    /// The request is malformed or has an invalid parameter.
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorResponse>),
+    /// Not logged in or wrong credentials.
+    #[oai(status = 401)]
+    Unauthorized(Json<ErrorResponse>),
+    /// The API token lacks the scope needed, the operation needs a session
+    /// or registration is not allowed.
+    #[oai(status = 403)]
+    Forbidden(Json<ErrorResponse>),
+    /// The key or job does not exist or belongs to another user.
+    #[oai(status = 404)]
+    NotFound(Json<ErrorResponse>),
+    /// A key with the label or a user with the name already exists.
+    #[oai(status = 409)]
+    Conflict(Json<ErrorResponse>),
+    #[oai(status = 500)]
+    Internal(Json<ErrorResponse>),
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let body = Json(ErrorResponse {
+            error: err.to_string(),
+        });
+        match err.status() {
+            StatusCode::BAD_REQUEST => ApiError::BadRequest(body),
+            StatusCode::UNAUTHORIZED => ApiError::Unauthorized(body),
+            StatusCode::FORBIDDEN => ApiError::Forbidden(body),
+            StatusCode::NOT_FOUND => ApiError::NotFound(body),
+            StatusCode::CONFLICT => ApiError::Conflict(body),
+            _ => ApiError::Internal(body),
+        }
+    }
+}
+
+fn bad_request(err: Error) -> ApiError {
+    ApiError::BadRequest(Json(ErrorResponse {
+        error: err.to_string(),
+    }))
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+fn unauthorized() -> Error {
+    Error::from_string("Not logged in", StatusCode::UNAUTHORIZED)
+}
+
+/// Caller of an operation, authenticated with a cookie session or an API token.
+struct Caller {
+    session_user: Option<UserId>,
+    token: Option<TokenAuth>,
+}
+
+impl<'a> FromRequest<'a> for Caller {
+    async fn from_request(req: &'a Request, _body: &mut RequestBody) -> poem::Result<Self> {
+        let token = req.extensions().get::<TokenAuth>().cloned();
+        let session = <&Session>::from_request_without_body(req).await?;
+        let state = req
+            .data::<Arc<Mutex<WebApp>>>()
+            .ok_or_else(|| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))?;
+        let session_user = state.lock().await.session_user(session);
+
+        Ok(Self {
+            session_user,
+            token,
+        })
+    }
+}
+
+impl Caller {
+    /// Returns the calling user. API tokens are accepted only when they have
+    /// the `scope`, operations without a scope need a cookie session.
+    fn user_id(&self, scope: Option<Scope>) -> poem::Result<UserId> {
+        if let Some(token) = &self.token {
+            return match scope {
+                Some(scope) if token.allows(&scope) => Ok(token.user_id),
+                Some(scope) => Err(Error::from_string(
+                    format!("API token lacks scope {scope}"),
+                    StatusCode::FORBIDDEN,
+                )),
+                None => Err(Error::from_string(
+                    "Operation needs a session, API tokens are not accepted",
+                    StatusCode::FORBIDDEN,
+                )),
+            };
+        }
+
+        self.session_user.ok_or_else(unauthorized)
+    }
+}
+
+struct Api;
+
+#[OpenApi(prefix_path = "/v1")]
+impl Api {
+    /// Log in
+    ///
+    /// Starts a cookie session used by the other endpoints.
+    #[oai(path = "/login", method = "post", tag = "ApiTags::Auth")]
+    async fn login(
+        &self,
+        req: Json<LoginRequest>,
+        session: &Session,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        passwords: Data<&Arc<PasswordHashing>>,
+    ) -> ApiResult<Json<UserResponse>> {
+        let user_id = authenticate(&req.username, &req.password, &db, &passwords)
+            .await
+            .map_err(|_| Error::from_string("Wrong credentials", StatusCode::UNAUTHORIZED))?;
+
+        state.lock().await.start_session(session, user_id);
+
+        Ok(Json(UserResponse {
+            user_id,
+            username: req.0.username,
+        }))
+    }
+
+    /// Register
+    ///
+    /// Creates an account when self-registration is enabled.
+    #[oai(path = "/register", method = "post", tag = "ApiTags::Auth")]
+    async fn register(
+        &self,
+        req: Json<RegisterRequest>,
+        Data(mode): Data<&RegistrationMode>,
+        db: Data<&SharedStorage>,
+        passwords: Data<&Arc<PasswordHashing>>,
+    ) -> ApiResult<UserCreated> {
+        let username = req.username.trim();
+        let user_id = register_user(
+            *mode,
+            username,
+            &req.password,
+            req.invite_code.as_deref(),
+            &db,
+            &passwords,
+        )
+        .await?;
+
+        Ok(UserCreated::Created(Json(UserResponse {
+            user_id,
+            username: username.to_string(),
+        })))
+    }
+
+    /// Log out
+    #[oai(path = "/logout", method = "post", tag = "ApiTags::Auth")]
+    async fn logout(&self, session: &Session, state: Data<&Arc<Mutex<WebApp>>>) -> NoContent {
+        state.lock().await.end_session(session);
+        NoContent::NoContent
+    }
+
+    /// List keys
+    ///
+    /// Keys of the logged in user with their public keys, oldest first.
+    #[oai(path = "/keys", method = "get", tag = "ApiTags::Keys")]
+    async fn list_keys(
+        &self,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<Json<Vec<KeyResponse>>> {
+        let user_id = caller.user_id(Some(Scope::ReadPublicKeys))?;
+        let keys = db.list_user_keys(user_id).await.map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        let mut res = Vec::with_capacity(keys.len());
+        for key in keys {
+            let sealed_key = db.get_user_key(user_id, key.id).await;
+            res.push(match sealed_key {
+                Ok((key, sealed_key)) => {
+                    key_response(user_id, key, &sealed_key, &keyring, &sign_service).await
+                }
+                Err(_) => KeyResponse {
+                    key_id: key.id,
+                    public_id: key.public_id,
+                    label: key.label,
+                    algorithm: key.algorithm,
+                    created_at: key.created_at,
+                    exportable: key.exportable,
+                    public_key: None,
+                    derived_addresses: Vec::new(),
+                },
+            });
+        }
+
+        Ok(Json(res))
+    }
+
+    /// Generate a key
+    #[oai(path = "/keys", method = "post", tag = "ApiTags::Keys")]
+    async fn generate_key(
+        &self,
+        req: Json<GenerateKeyRequest>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<KeyCreated> {
+        let user_id = caller.user_id(None)?;
+        let key_id = generate_user_key(
+            user_id,
+            req.label.trim(),
+            req.exportable,
+            &db,
+            &keyring,
+            &sign_service,
+        )
+        .await?;
+
+        let (_, key, sealed_key) = db.get_key(key_id).await.map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(KeyCreated::Created(Json(
+            key_response(user_id, key, &sealed_key, &keyring, &sign_service).await,
+        )))
+    }
+
+    /// Import a key
+    ///
+    /// Stores an existing secp256k1 private key, which is signed with like a
+    /// generated one.
+    #[oai(path = "/keys/import", method = "post", tag = "ApiTags::Keys")]
+    async fn import_key(
+        &self,
+        req: Json<ImportKeyRequest>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<KeyCreated> {
+        let user_id = caller.user_id(None)?;
+        let import = KeyImport {
+            label: req.label.trim(),
+            key: &req.key,
+            password: req.password.as_deref(),
+            exportable: req.exportable,
+        };
+        let key_id = import_user_key(user_id, import, &db, &keyring).await?;
+
+        let (_, key, sealed_key) = db.get_key(key_id).await.map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(KeyCreated::Created(Json(
+            key_response(user_id, key, &sealed_key, &keyring, &sign_service).await,
+        )))
+    }
+
+    /// Generate or import an HD key
+    ///
+    /// The key is the seed of a BIP-39 mnemonic, signing requests choose
+    /// its child key by a BIP-32 derivation path. A generated mnemonic is
+    /// returned once and not stored.
+    #[oai(path = "/keys/hd", method = "post", tag = "ApiTags::Keys")]
+    async fn add_hd_key(
+        &self,
+        req: Json<AddHdKeyRequest>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<HdKeyCreated> {
+        let user_id = caller.user_id(None)?;
+        let (key_id, mnemonic) = add_hd_user_key(
+            user_id,
+            req.label.trim(),
+            req.mnemonic.as_deref(),
+            &req.passphrase,
+            &db,
+            &keyring,
+            &sign_service,
+        )
+        .await?;
+
+        let (_, key, sealed_key) = db.get_key(key_id).await.map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+        Ok(HdKeyCreated::Created(Json(HdKeyResponse {
+            key: key_response(user_id, key, &sealed_key, &keyring, &sign_service).await,
+            mnemonic: mnemonic.map(|mnemonic| mnemonic.to_string()),
+        })))
+    }
+
+    /// Export a key
+    ///
+    /// Returns the key as an Ethereum keystore v3 file (Web3 Secret Storage)
+    /// encrypted under the keystore password. Needs a session and the
+    /// password of the account, HD keys are backed up by their mnemonic.
+    #[oai(path = "/keys/:key_id/export", method = "post", tag = "ApiTags::Keys")]
+    #[allow(clippy::too_many_arguments)]
+    async fn export_key(
+        &self,
+        key_id: Path<KeyId>,
+        req: Json<ExportKeyRequest>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        passwords: Data<&Arc<PasswordHashing>>,
+        key_export: Data<&KeyExportPolicy>,
+    ) -> ApiResult<Json<serde_json::Value>> {
+        let user_id = caller.user_id(None)?;
+        let export = KeyExport {
+            key_id: key_id.0,
+            password: &req.password,
+            keystore_password: &req.keystore_password,
+            kdf: req.kdf,
+        };
+        let (_, keystore) =
+            export_user_key(user_id, export, **key_export, &db, &keyring, &passwords).await?;
+
+        let keystore = serde_json::from_str(&keystore).map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        Ok(Json(keystore))
+    }
+
+    /// Make a key non-exportable
+    ///
+    /// The key can't be exported afterwards, this can't be undone.
+    #[oai(
+        path = "/keys/:key_id/non-exportable",
+        method = "post",
+        tag = "ApiTags::Keys"
+    )]
+    async fn lock_key_export(
+        &self,
+        key_id: Path<KeyId>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+    ) -> ApiResult<NoContent> {
+        let user_id = caller.user_id(None)?;
+        db.set_key_non_exportable(user_id, key_id.0)
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::NOT_FOUND))?;
+
+        Ok(NoContent::NoContent)
+    }
+
+    /// Discard a key
+    #[oai(path = "/keys/:key_id", method = "delete", tag = "ApiTags::Keys")]
+    async fn discard_key(
+        &self,
+        key_id: Path<KeyId>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+    ) -> ApiResult<NoContent> {
+        let user_id = caller.user_id(None)?;
+        db.discard_user_key(user_id, key_id.0)
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::NOT_FOUND))?;
+
+        Ok(NoContent::NoContent)
+    }
+
+    /// Sign a message
+    ///
+    /// Signing runs in the background, the returned job contains the
+    /// signature once it is done.
+    #[oai(path = "/sign", method = "post", tag = "ApiTags::Signing")]
+    async fn sign(
+        &self,
+        req: Json<SignRequest>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<SignAccepted> {
+        let user_id = caller.user_id(Some(Scope::Sign(Some(req.key_id))))?;
+        let options = SignOptions {
+            hash: message_hash(req.hash, req.prehashed, req.message_encoding),
+            encoding: req.encoding,
+            address_type: req.address_type,
+        };
+        check_message(req.scheme, &req.message)
+            .and_then(|_| check_sign_options(req.scheme, &options, &req.message))
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+        let (key, sealed_key) = db
+            .get_user_key(user_id, req.key_id)
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::NOT_FOUND))?;
+        check_derivation_path(&key.algorithm, req.derivation_path.as_deref())
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+
+        let now = unix_time_now();
+        let job = SignJob {
+            job_id: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+            user_id,
+            key_id: req.key_id,
+            scheme: req.scheme,
+            encoding: req.encoding,
+            hash: req.scheme.hashes_message().then_some(req.hash),
+            prehashed: req.prehashed,
+            message_encoding: req.message_encoding,
+            status: JobStatus::Pending,
+            signature: None,
+            digest: None,
+            bundle: None,
+            error: None,
+            created_at: now,
+        };
+        {
+            let mut state = state.lock().await;
+            state.sign_jobs.retain(|_, job| {
+                job.status == JobStatus::Pending || job.created_at + JOB_RETENTION_SECS > now
+            });
+            state.sign_jobs.insert(job.job_id.clone(), job.clone());
+        }
+
+        let username = db.get_user_name(user_id).await.unwrap_or_default();
+        let state = state.clone();
+        let keyring = keyring.clone();
+        let sign_service = sign_service.clone();
+        let job_id = job.job_id.clone();
+        let key_id = req.key_id;
+        let scheme = req.scheme;
+        let derivation_path = req.0.derivation_path;
+        let message = req.0.message;
+        tokio::spawn(async move {
+            let res = match keyring.open(user_id, &key, &sealed_key) {
+                Ok(secret) => {
+                    let sign_service = sign_service.lock().await;
+                    match sign_service.child_key(
+                        &key.algorithm,
+                        &secret,
+                        derivation_path.as_deref(),
+                    ) {
+                        Ok(child_key) => sign_service
+                            .sign_message(scheme, options, &message, &child_key)
+                            .await
+                            .and_then(|output| {
+                                let public_key = sign_service.public_key(&child_key)?;
+                                Ok(SignatureBundle::new(
+                                    scheme,
+                                    &options,
+                                    output,
+                                    &public_key,
+                                    key_id,
+                                    username,
+                                )
+                                .with_derivation_path(derivation_path.as_deref()))
+                            })
+                            .map_err(|err| err.to_string()),
+                        Err(err) => Err(err.to_string()),
+                    }
+                }
+                Err(err) => Err(err.to_string()),
+            };
+
+            if let Some(job) = state.lock().await.sign_jobs.get_mut(&job_id) {
+                match res {
+                    Ok(bundle) => {
+                        job.status = JobStatus::Done;
+                        job.signature = Some(bundle.signature.clone());
+                        job.digest = bundle.digest.clone();
+                        job.bundle = Some(bundle);
+                    }
+                    Err(err) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(err);
+                    }
+                }
+            }
+        });
+
+        Ok(SignAccepted::Accepted(Json(job)))
+    }
+
+    /// Sign a file
+    ///
+    /// The request body is the raw file content as
+    /// `application/octet-stream`. It is hashed while it is received and
+    /// the digest is signed pre-hashed, so files of any size can be signed.
+    /// Only `ecdsa`, `ecdsa-recoverable` and `bip340` sign binary data.
+    #[oai(path = "/sign/file", method = "post", tag = "ApiTags::Signing")]
+    #[allow(clippy::too_many_arguments)]
+    async fn sign_file(
+        &self,
+        key_id: Query<KeyId>,
+        /// Name of the file, it is only put into the signature.
+        filename: Query<String>,
+        /// BIP-32 path of the child key of an HD key.
+        derivation_path: Query<Option<String>>,
+        /// Defaults to `ecdsa`.
+        #[oai(default)]
+        scheme: Query<SignatureScheme>,
+        /// Defaults to `sha256`.
+        #[oai(default)]
+        hash: Query<HashFunction>,
+        encoding: Query<Option<SignatureEncoding>>,
+        body: Binary<Body>,
+        caller: Caller,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<Json<SignatureBundle>> {
+        let user_id = caller.user_id(Some(Scope::Sign(Some(key_id.0))))?;
+        let options = SignOptions {
+            hash: message_hash(hash.0, true, MessageEncoding::Text),
+            encoding: encoding.0,
+            ..SignOptions::default()
+        };
+        if !scheme.0.hashes_message() {
+            return Err(Error::from_string(
+                SignServiceError::BinaryMessage(scheme.0).to_string(),
+                StatusCode::BAD_REQUEST,
+            )
+            .into());
+        }
+        let (key, sealed_key) = db
+            .get_user_key(user_id, key_id.0)
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::NOT_FOUND))?;
+        check_derivation_path(&key.algorithm, derivation_path.as_deref())
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+
+        let (size, digest) = digest_reader(hash.0, body.0.into_async_read())
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+        let digest = hex::encode(digest);
+        check_sign_options(scheme.0, &options, &digest)
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+
+        let secret = keyring.open(user_id, &key, &sealed_key).map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        let sign_service = sign_service.lock().await;
+        let child_key = sign_service
+            .child_key(&key.algorithm, &secret, derivation_path.as_deref())
+            .map_err(|err| {
+                Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        let output = sign_service
+            .sign_message(scheme.0, options, &digest, &child_key)
+            .await
+            .map_err(|err| {
+                Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+
+        let public_key = sign_service.public_key(&child_key).map_err(|err| {
+            Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        let username = db.get_user_name(user_id).await.unwrap_or_default();
+
+        Ok(Json(
+            SignatureBundle::new(scheme.0, &options, output, &public_key, key_id.0, username)
+                .with_derivation_path(derivation_path.as_deref())
+                .with_file(filename.0, size),
+        ))
+    }
+
+    /// Sign EIP-712 typed data
+    ///
+    /// Same as signing with the `eip712` scheme, the typed data is given as
+    /// a JSON object instead of a string.
+    #[oai(path = "/sign/typed-data", method = "post", tag = "ApiTags::Signing")]
+    async fn sign_typed_data(
+        &self,
+        req: Json<SignTypedDataRequest>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<SignAccepted> {
+        let req = SignRequest::payload(
+            req.key_id,
+            req.0.derivation_path,
+            SignatureScheme::EthereumTypedData,
+            req.0.typed_data.to_string(),
+        );
+        self.sign(req, caller, state, db, keyring, sign_service)
+            .await
+    }
+
+    /// Sign an Ethereum transaction
+    ///
+    /// Same as signing with the `eth-tx` scheme, the transaction is given as
+    /// a JSON object instead of a string. The signature of the finished job
+    /// is the raw signed transaction for `eth_sendRawTransaction`.
+    #[oai(path = "/sign/transaction", method = "post", tag = "ApiTags::Signing")]
+    async fn sign_transaction(
+        &self,
+        req: Json<SignTransactionRequest>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<SignAccepted> {
+        let req = SignRequest::payload(
+            req.key_id,
+            req.0.derivation_path,
+            SignatureScheme::EthereumTransaction,
+            req.0.transaction.to_string(),
+        );
+        self.sign(req, caller, state, db, keyring, sign_service)
+            .await
+    }
+
+    /// Sign a PSBT
+    ///
+    /// Same as signing with the `psbt` scheme. The signature of the finished
+    /// job is the PSBT with partial signatures for all inputs of the key.
+    #[oai(path = "/sign/psbt", method = "post", tag = "ApiTags::Signing")]
+    async fn sign_psbt(
+        &self,
+        req: Json<SignPsbtRequest>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<SignAccepted> {
+        let req = SignRequest::payload(
+            req.key_id,
+            req.0.derivation_path,
+            SignatureScheme::BitcoinPsbt,
+            req.0.psbt,
+        );
+        self.sign(req, caller, state, db, keyring, sign_service)
+            .await
+    }
+
+    /// Sign a Nostr event
+    ///
+    /// Same as signing with the `nostr` scheme, the event is given as a JSON
+    /// object instead of a string. The signature of the finished job is the
+    /// complete signed event with `id`, `pubkey` and `sig`.
+    #[oai(path = "/sign/nostr-event", method = "post", tag = "ApiTags::Signing")]
+    async fn sign_nostr_event(
+        &self,
+        req: Json<SignNostrEventRequest>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+        db: Data<&SharedStorage>,
+        keyring: Data<&Arc<Keyring>>,
+        sign_service: Data<&Arc<Mutex<SignService>>>,
+    ) -> ApiResult<SignAccepted> {
+        let req = SignRequest::payload(
+            req.key_id,
+            req.0.derivation_path,
+            SignatureScheme::NostrEvent,
+            req.0.event.to_string(),
+        );
+        self.sign(req, caller, state, db, keyring, sign_service)
+            .await
+    }
+
+    /// Decode a PSBT
+    ///
+    /// Lists the inputs, outputs and the fee, e.g. to review them before
+    /// signing.
+    #[oai(path = "/psbt/decode", method = "post", tag = "ApiTags::Signing")]
+    async fn decode_psbt(
+        &self,
+        req: Json<DecodePsbtRequest>,
+    ) -> ApiResult<Json<DecodePsbtResponse>> {
+        let bad_request = |msg: String| Error::from_string(msg, StatusCode::BAD_REQUEST);
+        let psbt =
+            Psbt::parse(&req.psbt).map_err(|err| bad_request(format!("Invalid PSBT: {err}")))?;
+        let public_key = match &req.public_key {
+            Some(public_key) => decode_hex_or_base64(public_key)
+                .ok_or_else(|| bad_request(SignServiceError::InvalidPublicKey.to_string()))?,
+            None => Vec::new(),
+        };
+
+        Ok(Json(DecodePsbtResponse {
+            inputs: psbt
+                .input_views(&public_key)
+                .into_iter()
+                .map(|input| PsbtInput {
+                    outpoint: input.outpoint,
+                    address: input.address,
+                    value: input.value,
+                    value_verified: input.value_verified,
+                    signable: input.signable,
+                })
+                .collect(),
+            outputs: psbt
+                .output_views()
+                .into_iter()
+                .map(|output| PsbtOutput {
+                    address: output.address,
+                    value: output.value,
+                })
+                .collect(),
+            fee: psbt.fee(),
+            fee_verified: psbt.fee_verified(),
+        }))
+    }
+
+    /// Hash EIP-712 typed data
+    ///
+    /// Returns the domain separator, the message hash and the digest which
+    /// is signed, e.g. to check them before signing.
+    #[oai(path = "/typed-data/hash", method = "post", tag = "ApiTags::Signing")]
+    async fn hash_typed_data(
+        &self,
+        req: Json<TypedDataHashRequest>,
+    ) -> ApiResult<Json<TypedDataHashResponse>> {
+        let hashes = TypedData::parse(&req.0.typed_data.to_string()).and_then(|typed_data| {
+            Ok(TypedDataHashResponse {
+                domain_separator: format!("0x{}", hex::encode(typed_data.domain_separator()?)),
+                struct_hash: format!("0x{}", hex::encode(typed_data.struct_hash()?)),
+                digest: format!("0x{}", hex::encode(typed_data.signing_hash()?)),
+            })
+        });
+
+        hashes.map(Json).map_err(|err| {
+            Error::from_string(
+                format!("Invalid EIP-712 typed data: {err}"),
+                StatusCode::BAD_REQUEST,
+            )
+            .into()
+        })
+    }
+
+    /// Signing job status
+    #[oai(path = "/jobs/:job_id", method = "get", tag = "ApiTags::Signing")]
+    async fn job_status(
+        &self,
+        job_id: Path<String>,
+        caller: Caller,
+        state: Data<&Arc<Mutex<WebApp>>>,
+    ) -> ApiResult<Json<SignJob>> {
+        let not_found = || Error::from_string("Job not found", StatusCode::NOT_FOUND);
+        let job = state.lock().await.sign_jobs.get(&job_id.0).cloned();
+        let Some(job) = job else {
+            if caller.token.is_none() {
+                caller.user_id(None)?;
+            }
+            return Err(not_found().into());
+        };
+
+        // tokens need the scope of the key the job signs with
+        let scope = Scope::Sign(Some(job.key_id));
+        let user_id = caller.user_id(Some(scope))?;
+        if job.user_id != user_id {
+            return Err(not_found().into());
+        }
+
+        Ok(Json(job))
+    }
+
+    /// Verify a signature
+    ///
+    /// Does not need a session, an invalid signature is reported in the
+    /// response body. A signature bundle is verified on its own or against
+    /// the message.
+    #[oai(path = "/verify", method = "post", tag = "ApiTags::Signing")]
+    async fn verify(&self, req: Json<VerifyRequest>) -> ApiResult<Json<VerifyResponse>> {
+        let res = match (&req.bundle, &req.message, &req.signature, &req.public_key) {
+            (Some(bundle), message, _, _) => SignService::verify_bundle(
+                bundle,
+                message.as_deref(),
+                req.prehashed,
+                req.message_encoding,
+            ),
+            (None, Some(message), Some(signature), Some(public_key)) => {
+                SignService::verify_message(
+                    req.scheme,
+                    message_hash(req.hash, req.prehashed, req.message_encoding),
+                    message,
+                    signature,
+                    public_key,
+                )
+            }
+            _ => {
+                return Err(Error::from_string(
+                    "Either a bundle or the message, signature and public key are needed",
+                    StatusCode::BAD_REQUEST,
+                )
+                .into())
+            }
+        };
+
+        Ok(Json(VerifyResponse {
+            valid: res.is_ok(),
+            reason: res.err().map(|err| err.to_string()),
+        }))
+    }
+
+    /// Recover the public key
+    ///
+    /// Public key of the signer with its addresses, recovered from the
+    /// message and a recoverable signature. Does not need a session, the
+    /// recovered key has to be compared with the expected signer.
+    #[oai(path = "/recover", method = "post", tag = "ApiTags::Signing")]
+    async fn recover(&self, req: Json<RecoverRequest>) -> ApiResult<Json<PublicKeyInfo>> {
+        let public_key = SignService::recover_public_key(
+            req.scheme,
+            message_hash(req.hash, req.prehashed, req.message_encoding),
+            &req.message,
+            &req.signature,
+        )
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+
+        Ok(Json(public_key))
+    }
+}
+
+/// Errors outside of the API operations (unknown path, wrong method) are
+/// returned as JSON instead of the HTML error page.
+async fn json_error(err: Error) -> Response {
+    // errors returned by the operations already carry their JSON response
+    if !err.has_source() {
+        return err.into_response();
+    }
+    let status = err.status();
+    Json(ErrorResponse {
+        error: err.to_string(),
+    })
+    .with_status(status)
+    .into_response()
+}
+
+/// Routes of the JSON API nested under `/api`: the operations under `/v1`,
+/// the OpenAPI document at `/openapi.json` and Swagger UI at `/docs`.
+pub fn setup_route() -> Route {
+    let service = OpenApiService::new(Api, "waas", env!("CARGO_PKG_VERSION")).server("/api");
+
+    Route::new()
+        .at("/openapi.json", service.spec_endpoint())
+        .nest("/docs", service.swagger_ui())
+        .nest(
+            "/",
+            service
+                .into_endpoint()
+                .with(BearerAuth)
+                .catch_all_error(json_error),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caller_scopes() {
+        let token = |scopes| Caller {
+            session_user: Some(2),
+            token: Some(TokenAuth { user_id: 1, scopes }),
+        };
+
+        let caller = token(vec![Scope::Sign(Some(1))]);
+        assert_eq!(caller.user_id(Some(Scope::Sign(Some(1)))).unwrap(), 1);
+        for scope in [
+            Some(Scope::Sign(Some(2))),
+            Some(Scope::ReadPublicKeys),
+            None,
+        ] {
+            // a token is never upgraded to the session sent along with it
+            let err = caller.user_id(scope).unwrap_err();
+            assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        }
+
+        let caller = token(vec![Scope::Sign(None), Scope::ReadPublicKeys]);
+        assert_eq!(caller.user_id(Some(Scope::Sign(Some(2)))).unwrap(), 1);
+        assert_eq!(caller.user_id(Some(Scope::ReadPublicKeys)).unwrap(), 1);
+        assert!(caller.user_id(None).is_err());
+
+        let session = Caller {
+            session_user: Some(2),
+            token: None,
+        };
+        assert_eq!(session.user_id(None).unwrap(), 2);
+        assert_eq!(session.user_id(Some(Scope::Sign(Some(1)))).unwrap(), 2);
+
+        let anonymous = Caller {
+            session_user: None,
+            token: None,
+        };
+        let err = anonymous.user_id(None).unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+}
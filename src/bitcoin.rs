@@ -0,0 +1,366 @@
+//! Bitcoin addresses and signed messages, mainnet only.
+
+use bech32::{hrp, segwit, Fe32};
+use k256::ecdsa::VerifyingKey;
+use k256::elliptic_curve::PrimeField;
+use k256::{ProjectivePoint, Scalar};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+const P2PKH_VERSION: u8 = 0x00;
+const P2SH_VERSION: u8 = 0x05;
+/// Versions of WIF private keys of mainnet and testnet, the key itself is
+/// the same on both.
+const WIF_VERSIONS: [u8; 2] = [0x80, 0xef];
+/// Magic prefix of signed messages, including its length byte.
+const MESSAGE_MAGIC: &[u8] = b"\x18Bitcoin Signed Message:\n";
+
+/// `RIPEMD160(SHA256(data))`
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+/// `SHA256(SHA256(data))`
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// `SHA256(SHA256(tag) || SHA256(tag) || data)`, see BIP-340.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = Sha256::digest(tag.as_bytes());
+    Sha256::new()
+        .chain_update(tag)
+        .chain_update(tag)
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+/// x coordinate of the key, the public key of BIP-340 signatures.
+pub fn x_only(key: &VerifyingKey) -> [u8; 32] {
+    key.to_encoded_point(true).as_bytes()[1..]
+        .try_into()
+        .unwrap()
+}
+
+/// Output key of a Taproot key path only output, the internal key tweaked
+/// with `TapTweak` as in BIP-341 and BIP-86.
+pub fn taproot_output_key(key: &VerifyingKey) -> [u8; 32] {
+    let internal_key = x_only(key);
+    // the internal key is the point with even y
+    let even = VerifyingKey::from_sec1_bytes(&[&[0x02], internal_key.as_slice()].concat()).unwrap();
+    // a tweak above the curve order or an output key at infinity are
+    // practically impossible, BIP-341 lets them fail
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(
+        tagged_hash("TapTweak", &internal_key).into(),
+    ))
+    .expect("tweak below the curve order");
+    let output_key = ProjectivePoint::from(*even.as_affine()) + ProjectivePoint::GENERATOR * tweak;
+    x_only(&VerifyingKey::from_affine(output_key.to_affine()).expect("output key not at infinity"))
+}
+
+/// Appends a Bitcoin variable length integer (CompactSize).
+pub fn write_compact_size(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0xfc => out.push(n as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            out.push(0xfe);
+            out.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        _ => {
+            out.push(0xff);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+/// Hash of a message signed with `signmessage`, as used by BIP-137 and Electrum.
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = MESSAGE_MAGIC.to_vec();
+    write_compact_size(&mut data, message.len() as u64);
+    data.extend_from_slice(message);
+    sha256d(&data)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    P2pkh([u8; 20]),
+    P2sh([u8; 20]),
+    P2wpkh([u8; 20]),
+    /// Taproot output key.
+    P2tr([u8; 32]),
+}
+
+impl Address {
+    pub fn p2pkh(key: &VerifyingKey) -> Self {
+        Address::P2pkh(hash160(key.to_encoded_point(true).as_bytes()))
+    }
+
+    pub fn p2wpkh(key: &VerifyingKey) -> Self {
+        Address::P2wpkh(hash160(key.to_encoded_point(true).as_bytes()))
+    }
+
+    pub fn p2tr(key: &VerifyingKey) -> Self {
+        Address::P2tr(taproot_output_key(key))
+    }
+
+    /// P2WPKH nested in P2SH, the redeem script is `OP_0 <hash160(key)>`.
+    pub fn p2sh_p2wpkh(key: &VerifyingKey) -> Self {
+        Address::P2sh(hash160(&p2wpkh_script(key)))
+    }
+
+    /// Parses a base58check P2PKH or P2SH address, a bech32 P2WPKH or a
+    /// bech32m P2TR address.
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        if input.to_ascii_lowercase().starts_with("bc1") {
+            let (hrp, version, program) = segwit::decode(input).ok()?;
+            if hrp != hrp::BC {
+                return None;
+            }
+            return match version {
+                segwit::VERSION_0 => Some(Address::P2wpkh(program.try_into().ok()?)),
+                segwit::VERSION_1 => Some(Address::P2tr(program.try_into().ok()?)),
+                _ => None,
+            };
+        }
+
+        let decoded = bs58::decode(input).with_check(None).into_vec().ok()?;
+        let (version, hash) = decoded.split_first()?;
+        let hash = hash.try_into().ok()?;
+        match *version {
+            P2PKH_VERSION => Some(Address::P2pkh(hash)),
+            P2SH_VERSION => Some(Address::P2sh(hash)),
+            _ => None,
+        }
+    }
+
+    /// Whether the address pays to the key. Legacy addresses of
+    /// uncompressed keys are only matched when `compressed` is false.
+    pub fn is_of_key(&self, key: &VerifyingKey, compressed: bool) -> bool {
+        match self {
+            Address::P2pkh(hash) => *hash == hash160(key.to_encoded_point(compressed).as_bytes()),
+            Address::P2sh(_) | Address::P2wpkh(_) => {
+                compressed && (*self == Self::p2sh_p2wpkh(key) || *self == Self::p2wpkh(key))
+            }
+            Address::P2tr(_) => compressed && *self == Self::p2tr(key),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let base58 = |version: u8, hash: &[u8; 20]| {
+            bs58::encode([&[version], hash.as_slice()].concat())
+                .with_check()
+                .into_string()
+        };
+        match self {
+            Address::P2pkh(hash) => write!(f, "{}", base58(P2PKH_VERSION, hash)),
+            Address::P2sh(hash) => write!(f, "{}", base58(P2SH_VERSION, hash)),
+            Address::P2wpkh(hash) => match segwit::encode_v0(hrp::BC, hash) {
+                Ok(address) => write!(f, "{address}"),
+                Err(_) => Err(std::fmt::Error),
+            },
+            Address::P2tr(output_key) => match segwit::encode_v1(hrp::BC, output_key) {
+                Ok(address) => write!(f, "{address}"),
+                Err(_) => Err(std::fmt::Error),
+            },
+        }
+    }
+}
+
+/// Private key of a WIF encoded key, with or without the compressed flag.
+pub fn parse_wif(input: &str) -> Option<Zeroizing<[u8; 32]>> {
+    let decoded = Zeroizing::new(
+        bs58::decode(input.trim())
+            .with_check(None)
+            .into_vec()
+            .ok()?,
+    );
+    let (version, payload) = decoded.split_first()?;
+    if !WIF_VERSIONS.contains(version) {
+        return None;
+    }
+    let key = match payload {
+        [key @ .., 0x01] if key.len() == 32 => key,
+        key => key,
+    };
+    Some(Zeroizing::new(key.try_into().ok()?))
+}
+
+/// Address of a standard output script, segwit outputs of any version
+/// are shown in bech32 or bech32m.
+pub fn script_address(script: &[u8]) -> Option<String> {
+    match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] => {
+            Some(Address::P2pkh(hash.try_into().ok()?).to_string())
+        }
+        [0xa9, 0x14, hash @ .., 0x87] => Some(Address::P2sh(hash.try_into().ok()?).to_string()),
+        [version @ (0x00 | 0x51..=0x60), len, program @ ..] if *len as usize == program.len() => {
+            let version = match version {
+                0x00 => 0,
+                _ => version - 0x50,
+            };
+            segwit::encode(hrp::BC, Fe32::try_from(version).ok()?, program).ok()
+        }
+        _ => None,
+    }
+}
+
+/// `OP_DUP OP_HASH160 <hash160(key)> OP_EQUALVERIFY OP_CHECKSIG`, legacy
+/// outputs may pay to the uncompressed key.
+pub fn p2pkh_script(key: &VerifyingKey, compressed: bool) -> Vec<u8> {
+    [
+        &[0x76, 0xa9, 0x14],
+        hash160(key.to_encoded_point(compressed).as_bytes()).as_slice(),
+        &[0x88, 0xac],
+    ]
+    .concat()
+}
+
+pub fn is_p2pkh_script(script: &[u8]) -> bool {
+    matches!(script, [0x76, 0xa9, 0x14, .., 0x88, 0xac] if script.len() == 25)
+}
+
+/// `OP_0 <hash160(key)>`
+pub fn p2wpkh_script(key: &VerifyingKey) -> Vec<u8> {
+    [
+        &[0x00, 0x14],
+        hash160(key.to_encoded_point(true).as_bytes()).as_slice(),
+    ]
+    .concat()
+}
+
+/// Kind of address a signed message header byte commits to (BIP-137).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderKind {
+    P2pkhUncompressed,
+    P2pkhCompressed,
+    P2shP2wpkh,
+    P2wpkh,
+}
+
+/// Header byte of a compact signature with the recovery id, 31 to 34 for
+/// compressed keys is also what Electrum uses for all address types.
+pub fn signature_header(recovery_id: u8, kind: HeaderKind) -> u8 {
+    let base = match kind {
+        HeaderKind::P2pkhUncompressed => 27,
+        HeaderKind::P2pkhCompressed => 31,
+        HeaderKind::P2shP2wpkh => 35,
+        HeaderKind::P2wpkh => 39,
+    };
+    base + recovery_id
+}
+
+/// Splits a header byte into the recovery id and the kind of address.
+pub fn parse_signature_header(header: u8) -> Option<(u8, HeaderKind)> {
+    let kind = match header {
+        27..=30 => HeaderKind::P2pkhUncompressed,
+        31..=34 => HeaderKind::P2pkhCompressed,
+        35..=38 => HeaderKind::P2shP2wpkh,
+        39..=42 => HeaderKind::P2wpkh,
+        _ => return None,
+    };
+    Some(((header - 27) % 4, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BIP-86 internal key, output key and address of m/86'/0'/0'/0/0,
+    /// m/86'/0'/0'/0/1 and m/86'/0'/0'/1/0.
+    const BIP86_VECTORS: &[(&str, &str, &str)] = &[
+        (
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+            "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+        ),
+        (
+            "83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145",
+            "a82f29944d65b86ae6b5e5cc75e294ead6c59391a1edc5e016e3498c67fc7bbb",
+            "bc1p4qhjn9zdvkux4e44uhx8tc55attvtyu358kutcqkudyccelu0was9fqzwh",
+        ),
+        (
+            "399f1b2f4393f29a18c937859c5dd8a77350103157eb880f02e8c08214277cef",
+            "882d74e5d0572d5a816cef0041a96b6c1de832f6f9676d9605c44d5e9a97d3dc",
+            "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7",
+        ),
+    ];
+
+    /// Public keys and addresses of m/84'/0'/0'/0/0 and m/84'/0'/0'/0/1 of
+    /// BIP-84 and m/49'/1'/0'/0/0 of BIP-49, which is a testnet address.
+    const BIP84_VECTORS: &[(&str, &str)] = &[
+        (
+            "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c",
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+        ),
+        (
+            "03e775fd51f0dfb8cd865d9ff1cca2a158cf651fe997fdc9fee9c1d3b5e995ea77",
+            "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g",
+        ),
+    ];
+    const BIP49_VECTOR: (&str, &str) = (
+        "03a1af804ac108a8a51782198c2d034b28bf90c8803f5a53f76276fa69a4eae77f",
+        "2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2",
+    );
+
+    fn key(public_key: &str) -> VerifyingKey {
+        VerifyingKey::from_sec1_bytes(&hex::decode(public_key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn p2wpkh() {
+        for &(public_key, address) in BIP84_VECTORS {
+            let key = key(public_key);
+            assert_eq!(Address::p2wpkh(&key).to_string(), address);
+            assert_eq!(Address::parse(address), Some(Address::p2wpkh(&key)));
+            assert!(Address::parse(address).unwrap().is_of_key(&key, true));
+            assert!(!Address::parse(address).unwrap().is_of_key(&key, false));
+            assert_eq!(script_address(&p2wpkh_script(&key)).unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn p2sh_p2wpkh() {
+        let (public_key, testnet_address) = BIP49_VECTOR;
+        let key = key(public_key);
+        // testnet P2SH addresses have version 0xc4, the script hash is the same
+        let decoded = bs58::decode(testnet_address)
+            .with_check(Some(0xc4))
+            .into_vec()
+            .unwrap();
+        let hash: [u8; 20] = decoded[1..].try_into().unwrap();
+        assert_eq!(Address::p2sh_p2wpkh(&key), Address::P2sh(hash));
+
+        let address = Address::p2sh_p2wpkh(&key).to_string();
+        assert!(address.starts_with('3'));
+        assert_eq!(Address::parse(&address), Some(Address::P2sh(hash)));
+        assert!(Address::P2sh(hash).is_of_key(&key, true));
+    }
+
+    #[test]
+    fn bip86() {
+        for &(internal_key, output_key, address) in BIP86_VECTORS {
+            let internal_key = hex::decode(internal_key).unwrap();
+            // only the x coordinate is used, the key may have odd y
+            for parity in [0x02, 0x03] {
+                let key =
+                    VerifyingKey::from_sec1_bytes(&[&[parity], internal_key.as_slice()].concat())
+                        .unwrap();
+                assert_eq!(hex::encode(taproot_output_key(&key)), output_key);
+                assert_eq!(Address::p2tr(&key).to_string(), address);
+                assert!(Address::parse(address).unwrap().is_of_key(&key, true));
+            }
+
+            let script = hex::decode(format!("5120{output_key}")).unwrap();
+            assert_eq!(script_address(&script).unwrap(), address);
+        }
+    }
+}
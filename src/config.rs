@@ -0,0 +1,56 @@
+use super::keystore::KeyExportPolicy;
+use super::password::PasswordConfig;
+use super::registration::RegistrationMode;
+
+/// Runtime configuration, read from environment variables at startup.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Path of the SQLite database file (`WAAS_SQLITE_PATH`).
+    /// When not set, in-memory storage is used and all data is lost on restart.
+    pub sqlite_path: Option<String>,
+    /// Hashing of new passwords (`WAAS_PASSWORD_ALGORITHM`, `WAAS_BCRYPT_COST`,
+    /// `WAAS_ARGON2_MEMORY_KIB`, `WAAS_ARGON2_ITERATIONS`, `WAAS_ARGON2_PARALLELISM`).
+    pub password: PasswordConfig,
+    /// Who may create accounts (`WAAS_REGISTRATION`: `open`, `invite` or `admin`).
+    pub registration: RegistrationMode,
+    /// Whether keys can be exported to encrypted key files (`WAAS_KEY_EXPORT`:
+    /// `allowed` or `disabled`).
+    pub key_export: KeyExportPolicy,
+}
+
+/// Returns the value of a non-empty variable.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn parse_env_var<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env_var(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("Invalid value of {name}: {value}")),
+        None => Ok(default),
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let default = PasswordConfig::default();
+        let password = PasswordConfig {
+            algorithm: parse_env_var("WAAS_PASSWORD_ALGORITHM", default.algorithm)?,
+            bcrypt_cost: parse_env_var("WAAS_BCRYPT_COST", default.bcrypt_cost)?,
+            argon2_memory_kib: parse_env_var("WAAS_ARGON2_MEMORY_KIB", default.argon2_memory_kib)?,
+            argon2_iterations: parse_env_var("WAAS_ARGON2_ITERATIONS", default.argon2_iterations)?,
+            argon2_parallelism: parse_env_var(
+                "WAAS_ARGON2_PARALLELISM",
+                default.argon2_parallelism,
+            )?,
+        };
+
+        Ok(Self {
+            sqlite_path: env_var("WAAS_SQLITE_PATH"),
+            password,
+            registration: parse_env_var("WAAS_REGISTRATION", RegistrationMode::default())?,
+            key_export: parse_env_var("WAAS_KEY_EXPORT", KeyExportPolicy::default())?,
+        })
+    }
+}
@@ -1,70 +1,684 @@
 use std::collections::hash_map::*;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::keyring::SealedKey;
+
+mod sqlite;
+
+pub use sqlite::SqliteDb;
 
 #[derive(Clone, Debug)]
 pub enum DbError {
     UserNotFound,
+    UserAlreadyExists,
     WrongPassword,
-    KeyNotFound
+    KeyNotFound,
+    KeyAlreadyExists,
+    TokenNotFound,
+    InviteNotFound,
+    Storage(String),
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::UserNotFound => write!(f, "User not found"),
+            DbError::UserAlreadyExists => write!(f, "User already exists"),
+            DbError::WrongPassword => write!(f, "Wrong password"),
+            DbError::KeyNotFound => write!(f, "Key not found"),
+            DbError::KeyAlreadyExists => write!(f, "Key with this label already exists"),
+            DbError::TokenNotFound => write!(f, "API token not found"),
+            DbError::InviteNotFound => write!(f, "Invite code is invalid or already used"),
+            DbError::Storage(err) => write!(f, "Storage error: {err}"),
+        }
+    }
 }
 
 pub type UserId = u64;
+pub type KeyId = u64;
+pub type TokenId = u64;
+
+/// Public metadata of a stored key.
+#[derive(Clone, Debug)]
+pub struct KeyInfo {
+    pub id: KeyId,
+    pub label: String,
+    pub algorithm: String,
+    /// Creation time in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Whether the key may leave waas in an encrypted key file.
+    pub exportable: bool,
+    /// Unguessable id under which the public key is served to anyone.
+    pub public_id: String,
+}
+
+/// API token of a user, the token itself is only stored hashed.
+#[derive(Clone, Debug)]
+pub struct ApiTokenInfo {
+    pub id: TokenId,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Creation time in seconds since the Unix epoch.
+    pub created_at: u64,
+    pub last_used_at: Option<u64>,
+}
+
+/// Storage backend shared by all handlers. The backend does blocking I/O, so
+/// it is only used from blocking tasks and never on the async runtime.
+#[derive(Clone)]
+pub struct SharedStorage(Arc<Mutex<dyn Storage + Send>>);
+
+impl SharedStorage {
+    pub fn new(storage: impl Storage + Send + 'static) -> Self {
+        SharedStorage(Arc::new(Mutex::new(storage)))
+    }
+
+    /// Runs `f` with the backend in a blocking task.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Storage) -> T + Send + 'static,
+    ) -> T {
+        let storage = self.0.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            // every operation is complete or not done at all, so a panic leaves the backend usable
+            let mut storage = storage.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut *storage)
+        });
+        match task.await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    pub async fn get_user_password_hash(&self, user: &str) -> Result<(UserId, String), DbError> {
+        let user = user.to_string();
+        self.run(move |db| db.get_user_password_hash(&user)).await
+    }
+
+    pub async fn set_user_password_hash(
+        &self,
+        user_id: UserId,
+        password_hash: &str,
+    ) -> Result<(), DbError> {
+        let password_hash = password_hash.to_string();
+        self.run(move |db| db.set_user_password_hash(user_id, &password_hash))
+            .await
+    }
+
+    pub async fn add_user(
+        &self,
+        user: &str,
+        password_hash: &str,
+        invite_hash: Option<&str>,
+    ) -> Result<UserId, DbError> {
+        let (user, password_hash) = (user.to_string(), password_hash.to_string());
+        let invite_hash = invite_hash.map(str::to_string);
+        self.run(move |db| db.add_user(&user, &password_hash, invite_hash.as_deref()))
+            .await
+    }
+
+    pub async fn add_invite(&self, invite_hash: &str) -> Result<(), DbError> {
+        let invite_hash = invite_hash.to_string();
+        self.run(move |db| db.add_invite(&invite_hash)).await
+    }
+
+    pub async fn get_user_key(
+        &self,
+        user_id: UserId,
+        key_id: KeyId,
+    ) -> Result<(KeyInfo, SealedKey), DbError> {
+        self.run(move |db| db.get_user_key(user_id, key_id)).await
+    }
+
+    pub async fn get_key(&self, key_id: KeyId) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        self.run(move |db| db.get_key(key_id)).await
+    }
+
+    pub async fn get_key_by_public_id(
+        &self,
+        public_id: &str,
+    ) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        let public_id = public_id.to_string();
+        self.run(move |db| db.get_key_by_public_id(&public_id))
+            .await
+    }
+
+    pub async fn list_user_keys(&self, user_id: UserId) -> Result<Vec<KeyInfo>, DbError> {
+        self.run(move |db| db.list_user_keys(user_id)).await
+    }
+
+    pub async fn get_user_name(&self, user_id: UserId) -> Option<String> {
+        self.run(move |db| db.get_user_name(user_id)).await
+    }
+
+    pub async fn add_user_key(
+        &self,
+        user_id: UserId,
+        label: &str,
+        algorithm: &str,
+        exportable: bool,
+        seal: impl Fn(KeyId) -> Result<SealedKey, String> + Send + 'static,
+    ) -> Result<KeyId, DbError> {
+        let (label, algorithm) = (label.to_string(), algorithm.to_string());
+        self.run(move |db| db.add_user_key(user_id, &label, &algorithm, exportable, &seal))
+            .await
+    }
+
+    pub async fn discard_user_key(&self, user_id: UserId, key_id: KeyId) -> Result<(), DbError> {
+        self.run(move |db| db.discard_user_key(user_id, key_id))
+            .await
+    }
+
+    pub async fn set_key_non_exportable(
+        &self,
+        user_id: UserId,
+        key_id: KeyId,
+    ) -> Result<(), DbError> {
+        self.run(move |db| db.set_key_non_exportable(user_id, key_id))
+            .await
+    }
+
+    pub async fn add_api_token(
+        &self,
+        user_id: UserId,
+        name: &str,
+        token_hash: &str,
+        scopes: &[String],
+    ) -> Result<TokenId, DbError> {
+        let (name, token_hash) = (name.to_string(), token_hash.to_string());
+        let scopes = scopes.to_vec();
+        self.run(move |db| db.add_api_token(user_id, &name, &token_hash, &scopes))
+            .await
+    }
+
+    pub async fn list_api_tokens(&self, user_id: UserId) -> Result<Vec<ApiTokenInfo>, DbError> {
+        self.run(move |db| db.list_api_tokens(user_id)).await
+    }
+
+    pub async fn find_api_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<(UserId, ApiTokenInfo), DbError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |db| db.find_api_token(&token_hash)).await
+    }
+
+    pub async fn touch_api_token(&self, token_id: TokenId) -> Result<(), DbError> {
+        self.run(move |db| db.touch_api_token(token_id)).await
+    }
+
+    pub async fn revoke_api_token(
+        &self,
+        user_id: UserId,
+        token_id: TokenId,
+    ) -> Result<(), DbError> {
+        self.run(move |db| db.revoke_api_token(user_id, token_id))
+            .await
+    }
+}
+
+/// Operations the web application needs from a storage backend.
+pub trait Storage {
+    /// Returns the user with the stored hash of their password.
+    fn get_user_password_hash(&self, user: &str) -> Result<(UserId, String), DbError>;
+
+    fn set_user_password_hash(
+        &mut self,
+        user_id: UserId,
+        password_hash: &str,
+    ) -> Result<(), DbError>;
+
+    /// Creates a user, usernames are unique. When an invite hash is given,
+    /// the invite is used up by the new user or the user is not created.
+    fn add_user(
+        &mut self,
+        user: &str,
+        password_hash: &str,
+        invite_hash: Option<&str>,
+    ) -> Result<UserId, DbError>;
+
+    fn add_invite(&mut self, invite_hash: &str) -> Result<(), DbError>;
+
+    fn get_user_key(&self, user_id: UserId, key_id: KeyId)
+        -> Result<(KeyInfo, SealedKey), DbError>;
+
+    /// Returns the key together with its owner, regardless of who asks for it.
+    fn get_key(&self, key_id: KeyId) -> Result<(UserId, KeyInfo, SealedKey), DbError>;
+
+    /// Returns the key with the [public id](KeyInfo::public_id) together with its owner.
+    fn get_key_by_public_id(
+        &self,
+        public_id: &str,
+    ) -> Result<(UserId, KeyInfo, SealedKey), DbError>;
+
+    /// Returns keys of the user ordered by creation time.
+    fn list_user_keys(&self, user_id: UserId) -> Result<Vec<KeyInfo>, DbError>;
+
+    fn get_user_name(&self, user_id: UserId) -> Option<String>;
+
+    /// Stores a new key of the user, labels are unique per user. The key is
+    /// sealed by `seal` once its id is known, nothing is stored when it fails.
+    fn add_user_key(
+        &mut self,
+        user_id: UserId,
+        label: &str,
+        algorithm: &str,
+        exportable: bool,
+        seal: &dyn Fn(KeyId) -> Result<SealedKey, String>,
+    ) -> Result<KeyId, DbError>;
+
+    fn discard_user_key(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError>;
+
+    /// Forbids export of the key, there is no way back.
+    fn set_key_non_exportable(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError>;
+
+    /// Returns keys of all users, used when rewrapping keys under a new KEK.
+    fn list_sealed_keys(&self) -> Result<Vec<(UserId, KeyInfo, SealedKey)>, DbError>;
+
+    fn update_sealed_key(&mut self, key_id: KeyId, key: &SealedKey) -> Result<(), DbError>;
+
+    fn add_api_token(
+        &mut self,
+        user_id: UserId,
+        name: &str,
+        token_hash: &str,
+        scopes: &[String],
+    ) -> Result<TokenId, DbError>;
 
+    /// Returns tokens of the user ordered by creation time.
+    fn list_api_tokens(&self, user_id: UserId) -> Result<Vec<ApiTokenInfo>, DbError>;
+
+    /// Looks up a token by its hash, returns it together with its owner.
+    fn find_api_token(&self, token_hash: &str) -> Result<(UserId, ApiTokenInfo), DbError>;
+
+    /// Records that the token has just been used.
+    fn touch_api_token(&mut self, token_id: TokenId) -> Result<(), DbError>;
+
+    fn revoke_api_token(&mut self, user_id: UserId, token_id: TokenId) -> Result<(), DbError>;
+}
+
+pub fn unix_time_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// New public id of a key, 16 random bytes in hex.
+pub fn new_public_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// In-memory storage, everything is lost on restart.
 pub struct MemDb {
     users: HashMap<String, (UserId, String)>,
-    keys: HashMap<UserId, Vec<u8>>,
+    keys: HashMap<KeyId, (UserId, KeyInfo, SealedKey)>,
+    next_key_id: KeyId,
+    /// Hash of the invite code and whether it has been used.
+    invites: HashMap<String, bool>,
+    tokens: HashMap<TokenId, (UserId, String, ApiTokenInfo)>,
+    next_token_id: TokenId,
 }
 
 impl MemDb {
     pub fn new() -> Self {
         Self {
-            users: HashMap::from([
-                ("user1".to_string(), (1, "$2y$05$gifLHpZdNAixJzy36HyOc.1PsRNbn5Je9vlWalKyg3sGqSAW.8rFG".to_string())),
-                ("user2".to_string(), (2, "$2y$05$gifLHpZdNAixJzy36HyOc.ge.9FMFAI.6NwvXHqIpLQpCF3hepE9e".to_string())),
-            ]),
-// 123456 $2y$05$gifLHpZdNAixJzy36HyOc.1PsRNbn5Je9vlWalKyg3sGqSAW.8rFG
-// Alex5 $2y$05$gifLHpZdNAixJzy36HyOc.ge.9FMFAI.6NwvXHqIpLQpCF3hepE9e
-            keys: HashMap::new()
+            users: HashMap::new(),
+            keys: HashMap::new(),
+            next_key_id: 1,
+            invites: HashMap::new(),
+            tokens: HashMap::new(),
+            next_token_id: 1,
         }
     }
+}
+
+impl Storage for MemDb {
+    fn get_user_password_hash(&self, user: &str) -> Result<(UserId, String), DbError> {
+        self.users.get(user).cloned().ok_or(DbError::UserNotFound)
+    }
 
-    pub fn validate_user_password(&self, user: &str, password_hash: &str) -> Result<UserId, DbError> {
-        if let Some(v) = self.users.get(user) {
-            if v.1 == password_hash {
-                Ok(v.0)
-            } else {
-                Err(DbError::WrongPassword)
+    fn set_user_password_hash(
+        &mut self,
+        user_id: UserId,
+        password_hash: &str,
+    ) -> Result<(), DbError> {
+        let user = self
+            .users
+            .values_mut()
+            .find(|v| v.0 == user_id)
+            .ok_or(DbError::UserNotFound)?;
+        user.1 = password_hash.to_string();
+        Ok(())
+    }
+
+    fn add_user(
+        &mut self,
+        user: &str,
+        password_hash: &str,
+        invite_hash: Option<&str>,
+    ) -> Result<UserId, DbError> {
+        if self.users.contains_key(user) {
+            return Err(DbError::UserAlreadyExists);
+        }
+        if let Some(invite_hash) = invite_hash {
+            match self.invites.get_mut(invite_hash) {
+                Some(used) if !*used => *used = true,
+                _ => return Err(DbError::InviteNotFound),
             }
-        } else {
-            Err(DbError::UserNotFound)
         }
+
+        let user_id = self.users.values().map(|v| v.0).max().unwrap_or_default() + 1;
+        self.users
+            .insert(user.to_string(), (user_id, password_hash.to_string()));
+        Ok(user_id)
     }
 
-    pub fn get_user_key(&self, user_id: UserId) -> Result<Vec<u8>, DbError> {
-        self.keys.get(&user_id).ok_or(DbError::KeyNotFound).cloned()
+    fn add_invite(&mut self, invite_hash: &str) -> Result<(), DbError> {
+        self.invites.insert(invite_hash.to_string(), false);
+        Ok(())
+    }
+
+    fn get_user_key(
+        &self,
+        user_id: UserId,
+        key_id: KeyId,
+    ) -> Result<(KeyInfo, SealedKey), DbError> {
+        self.keys
+            .get(&key_id)
+            .filter(|k| k.0 == user_id)
+            .map(|k| (k.1.clone(), k.2.clone()))
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    fn get_key(&self, key_id: KeyId) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        self.keys.get(&key_id).cloned().ok_or(DbError::KeyNotFound)
+    }
+
+    fn get_key_by_public_id(
+        &self,
+        public_id: &str,
+    ) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        self.keys
+            .values()
+            .find(|k| k.1.public_id == public_id)
+            .cloned()
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    fn list_user_keys(&self, user_id: UserId) -> Result<Vec<KeyInfo>, DbError> {
+        let mut keys: Vec<KeyInfo> = self
+            .keys
+            .values()
+            .filter(|k| k.0 == user_id)
+            .map(|k| k.1.clone())
+            .collect();
+        keys.sort_by_key(|k| (k.created_at, k.id));
+        Ok(keys)
     }
 
-    pub fn get_user_name(&self, user_id: UserId) -> Option<String> {
-        self.users.iter().find_map(|i| { if i.1.0 == user_id { Some(i.0) } else { None } }).cloned()
+    fn get_user_name(&self, user_id: UserId) -> Option<String> {
+        self.users
+            .iter()
+            .find_map(|i| if i.1 .0 == user_id { Some(i.0) } else { None })
+            .cloned()
     }
 
-    // pub fn _add_user(&mut self, user: &str, password_hash: &str) -> Result<UserId, DbError> {
-    //     if self.users.contains_key(user) {
-    //         Err(DbError::UserAlreadyExists)
-    //     } else {
-    //         let mut rng = rand::thread_rng();
-    //         let user_id: UserId = rng.gen();
-    //         self.users.insert(user.to_string(), (user_id, password_hash.to_string()));
-    //         Ok(user_id)
-    //     }
-    // }
+    fn add_user_key(
+        &mut self,
+        user_id: UserId,
+        label: &str,
+        algorithm: &str,
+        exportable: bool,
+        seal: &dyn Fn(KeyId) -> Result<SealedKey, String>,
+    ) -> Result<KeyId, DbError> {
+        if !self.users.values().any(|v| v.0 == user_id) {
+            return Err(DbError::UserNotFound);
+        }
+        if self
+            .keys
+            .values()
+            .any(|k| k.0 == user_id && k.1.label == label)
+        {
+            return Err(DbError::KeyAlreadyExists);
+        }
+
+        let key_id = self.next_key_id;
+        let key = seal(key_id).map_err(DbError::Storage)?;
+        self.next_key_id += 1;
+        let info = KeyInfo {
+            id: key_id,
+            label: label.to_string(),
+            algorithm: algorithm.to_string(),
+            created_at: unix_time_now(),
+            exportable,
+            public_id: new_public_id(),
+        };
+        self.keys.insert(key_id, (user_id, info, key));
+        Ok(key_id)
+    }
+
+    fn discard_user_key(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError> {
+        match self.keys.get(&key_id) {
+            Some(k) if k.0 == user_id => {
+                self.keys.remove(&key_id);
+                Ok(())
+            }
+            _ => Err(DbError::KeyNotFound),
+        }
+    }
 
-    pub fn add_user_key(&mut self, user_id: UserId, key: &[u8]) -> Result<(), DbError> {
-        self.keys.insert(user_id, key.to_vec());
+    fn set_key_non_exportable(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError> {
+        match self.keys.get_mut(&key_id) {
+            Some(k) if k.0 == user_id => {
+                k.1.exportable = false;
+                Ok(())
+            }
+            _ => Err(DbError::KeyNotFound),
+        }
+    }
+
+    fn list_sealed_keys(&self) -> Result<Vec<(UserId, KeyInfo, SealedKey)>, DbError> {
+        Ok(self.keys.values().cloned().collect())
+    }
+
+    fn update_sealed_key(&mut self, key_id: KeyId, key: &SealedKey) -> Result<(), DbError> {
+        let k = self.keys.get_mut(&key_id).ok_or(DbError::KeyNotFound)?;
+        k.2 = key.clone();
         Ok(())
     }
 
-    pub fn discard_user_key(&mut self, user_id: UserId) -> Result<(), DbError> {
-        self.keys.remove(&user_id);
+    fn add_api_token(
+        &mut self,
+        user_id: UserId,
+        name: &str,
+        token_hash: &str,
+        scopes: &[String],
+    ) -> Result<TokenId, DbError> {
+        let token_id = self.next_token_id;
+        self.next_token_id += 1;
+        let info = ApiTokenInfo {
+            id: token_id,
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            created_at: unix_time_now(),
+            last_used_at: None,
+        };
+        self.tokens
+            .insert(token_id, (user_id, token_hash.to_string(), info));
+        Ok(token_id)
+    }
+
+    fn list_api_tokens(&self, user_id: UserId) -> Result<Vec<ApiTokenInfo>, DbError> {
+        let mut tokens: Vec<ApiTokenInfo> = self
+            .tokens
+            .values()
+            .filter(|t| t.0 == user_id)
+            .map(|t| t.2.clone())
+            .collect();
+        tokens.sort_by_key(|t| (t.created_at, t.id));
+        Ok(tokens)
+    }
+
+    fn find_api_token(&self, token_hash: &str) -> Result<(UserId, ApiTokenInfo), DbError> {
+        self.tokens
+            .values()
+            .find(|t| t.1 == token_hash)
+            .map(|t| (t.0, t.2.clone()))
+            .ok_or(DbError::TokenNotFound)
+    }
+
+    fn touch_api_token(&mut self, token_id: TokenId) -> Result<(), DbError> {
+        let t = self
+            .tokens
+            .get_mut(&token_id)
+            .ok_or(DbError::TokenNotFound)?;
+        t.2.last_used_at = Some(unix_time_now());
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn revoke_api_token(&mut self, user_id: UserId, token_id: TokenId) -> Result<(), DbError> {
+        match self.tokens.get(&token_id) {
+            Some(t) if t.0 == user_id => {
+                self.tokens.remove(&token_id);
+                Ok(())
+            }
+            _ => Err(DbError::TokenNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(key_id: KeyId) -> Result<SealedKey, String> {
+        Ok(SealedKey {
+            kek_id: "kek".to_string(),
+            ciphertext: key_id.to_be_bytes().to_vec(),
+        })
+    }
+
+    /// Behaviour both backends have to share.
+    pub(super) fn check_storage(db: &mut dyn Storage) {
+        let alice = db.add_user("alice", "hash", None).unwrap();
+        assert!(matches!(
+            db.add_user("alice", "other", None),
+            Err(DbError::UserAlreadyExists)
+        ));
+        assert_eq!(
+            db.get_user_password_hash("alice").unwrap(),
+            (alice, "hash".to_string())
+        );
+        db.set_user_password_hash(alice, "rehashed").unwrap();
+        assert_eq!(db.get_user_password_hash("alice").unwrap().1, "rehashed");
+        assert_eq!(db.get_user_name(alice).as_deref(), Some("alice"));
+        assert!(matches!(
+            db.get_user_password_hash("bob"),
+            Err(DbError::UserNotFound)
+        ));
+
+        let key_id = db
+            .add_user_key(alice, "main", "secp256k1", true, &sealed)
+            .unwrap();
+        assert!(matches!(
+            db.add_user_key(alice, "main", "secp256k1", true, &sealed),
+            Err(DbError::KeyAlreadyExists)
+        ));
+        assert!(matches!(
+            db.add_user_key(9999, "main", "secp256k1", true, &sealed),
+            Err(DbError::UserNotFound)
+        ));
+        assert!(matches!(
+            db.add_user_key(alice, "failed", "secp256k1", true, &|_| Err(
+                "no".to_string()
+            )),
+            Err(DbError::Storage(_))
+        ));
+        // the key is sealed for the id it is stored under
+        let (key, sealed_key) = db.get_user_key(alice, key_id).unwrap();
+        assert_eq!(key.label, "main");
+        assert!(key.exportable);
+        assert_eq!(sealed_key.ciphertext, key_id.to_be_bytes());
+        assert_eq!(db.get_key(key_id).unwrap().0, alice);
+        // public ids are random and find the key without knowing its owner
+        assert_eq!(key.public_id.len(), 32);
+        let (owner, public_key, _) = db.get_key_by_public_id(&key.public_id).unwrap();
+        assert_eq!((owner, public_key.id), (alice, key_id));
+        assert!(matches!(
+            db.get_key_by_public_id(&key_id.to_string()),
+            Err(DbError::KeyNotFound)
+        ));
+        assert_eq!(
+            db.list_user_keys(alice)
+                .unwrap()
+                .iter()
+                .map(|key| key.label.as_str())
+                .collect::<Vec<_>>(),
+            ["main"]
+        );
+
+        db.set_key_non_exportable(alice, key_id).unwrap();
+        assert!(!db.get_user_key(alice, key_id).unwrap().0.exportable);
+        assert!(matches!(
+            db.discard_user_key(alice + 1, key_id),
+            Err(DbError::KeyNotFound)
+        ));
+        db.discard_user_key(alice, key_id).unwrap();
+        assert!(matches!(
+            db.get_user_key(alice, key_id),
+            Err(DbError::KeyNotFound)
+        ));
+        // ids of discarded keys are not reused
+        assert!(
+            db.add_user_key(alice, "next", "secp256k1", true, &sealed)
+                .unwrap()
+                > key_id
+        );
+
+        let token_id = db
+            .add_api_token(alice, "ci", "token-hash", &["sign:*".to_string()])
+            .unwrap();
+        let (owner, token) = db.find_api_token("token-hash").unwrap();
+        assert_eq!(
+            (owner, token.id, token.scopes),
+            (alice, token_id, vec!["sign:*".to_string()])
+        );
+        db.touch_api_token(token_id).unwrap();
+        assert!(db.list_api_tokens(alice).unwrap()[0].last_used_at.is_some());
+        db.revoke_api_token(alice, token_id).unwrap();
+        assert!(matches!(
+            db.find_api_token("token-hash"),
+            Err(DbError::TokenNotFound)
+        ));
+
+        db.add_invite("invite-hash").unwrap();
+        db.add_user("carol", "hash", Some("invite-hash")).unwrap();
+        assert!(matches!(
+            db.add_user("dave", "hash", Some("invite-hash")),
+            Err(DbError::InviteNotFound)
+        ));
+        assert!(db.get_user_password_hash("dave").is_err());
+    }
+
+    #[test]
+    fn mem_storage() {
+        check_storage(&mut MemDb::new());
+    }
+
+    #[tokio::test]
+    async fn shared_storage() {
+        let db = SharedStorage::new(MemDb::new());
+        let alice = db.add_user("alice", "hash", None).await.unwrap();
+        assert_eq!(db.get_user_name(alice).await.as_deref(), Some("alice"));
+
+        // a panic reaches the caller and the storage stays usable
+        let other = db.clone();
+        let panicked = tokio::spawn(async move { other.run(|_| panic!("failed")).await }).await;
+        assert!(panicked.unwrap_err().is_panic());
+        assert_eq!(
+            db.get_user_password_hash("alice").await.unwrap(),
+            (alice, "hash".to_string())
+        );
+    }
+}
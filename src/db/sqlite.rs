@@ -0,0 +1,553 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    new_public_id, unix_time_now, ApiTokenInfo, DbError, KeyId, KeyInfo, SealedKey, Storage,
+    TokenId, UserId,
+};
+
+/// Schema migrations, applied in order. Index + 1 is the schema version
+/// stored in `PRAGMA user_version` once the migration has been applied.
+/// Never edit an already released migration, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // v1: users and their signing keys, accounts are added with `waas add-user`
+    r##"
+    CREATE TABLE users (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL
+    );
+    CREATE TABLE keys (
+        user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+        key BLOB NOT NULL
+    );
+    "##,
+    // v2: keys are encrypted, empty kek_id marks keys stored in plaintext by v1
+    r##"
+    ALTER TABLE keys ADD COLUMN kek_id TEXT NOT NULL DEFAULT '';
+    "##,
+    // v3: multiple labeled keys per user
+    r##"
+    CREATE TABLE user_keys (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        label TEXT NOT NULL,
+        algorithm TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        kek_id TEXT NOT NULL,
+        key BLOB NOT NULL,
+        UNIQUE (user_id, label)
+    );
+    INSERT INTO user_keys (user_id, label, algorithm, created_at, kek_id, key)
+        SELECT user_id, 'default', 'secp256k1', CAST(strftime('%s', 'now') AS INTEGER), kek_id, key
+        FROM keys;
+    DROP TABLE keys;
+    ALTER TABLE user_keys RENAME TO keys;
+    "##,
+    // v4: keys are served publicly by an unguessable id instead of their row id
+    r##"
+    ALTER TABLE keys ADD COLUMN public_id TEXT NOT NULL DEFAULT '';
+    UPDATE keys SET public_id = lower(hex(randomblob(16)));
+    CREATE UNIQUE INDEX keys_public_id ON keys (public_id);
+    "##,
+    // v5: API tokens, key ids are never reused so a token scoped to a
+    // discarded key can't sign with a key generated later
+    r##"
+    CREATE TABLE user_keys (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        label TEXT NOT NULL,
+        algorithm TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        kek_id TEXT NOT NULL,
+        key BLOB NOT NULL,
+        public_id TEXT NOT NULL UNIQUE,
+        UNIQUE (user_id, label)
+    );
+    INSERT INTO user_keys (id, user_id, label, algorithm, created_at, kek_id, key, public_id)
+        SELECT id, user_id, label, algorithm, created_at, kek_id, key, public_id FROM keys;
+    DROP TABLE keys;
+    ALTER TABLE user_keys RENAME TO keys;
+    CREATE TABLE api_tokens (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        token_hash TEXT NOT NULL UNIQUE,
+        scopes TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        last_used_at INTEGER
+    );
+    "##,
+    // v6: single-use invite codes for registration
+    r##"
+    CREATE TABLE invites (
+        code_hash TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        used_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+        used_at INTEGER
+    );
+    "##,
+    // v7: keys that must not be exported
+    r##"
+    ALTER TABLE keys ADD COLUMN exportable INTEGER NOT NULL DEFAULT 1;
+    "##,
+];
+
+/// Persistent storage kept in a SQLite database file.
+pub struct SqliteDb {
+    conn: Connection,
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(err: rusqlite::Error) -> Self {
+        DbError::Storage(err.to_string())
+    }
+}
+
+/// Key with its owner from the columns `user_id, id, label, algorithm,
+/// created_at, exportable, public_id, kek_id, key`.
+fn key_from_row(row: &rusqlite::Row) -> rusqlite::Result<(UserId, KeyInfo, SealedKey)> {
+    Ok((
+        row.get::<_, i64>(0)? as UserId,
+        KeyInfo {
+            id: row.get::<_, i64>(1)? as KeyId,
+            label: row.get(2)?,
+            algorithm: row.get(3)?,
+            created_at: row.get::<_, i64>(4)? as u64,
+            exportable: row.get(5)?,
+            public_id: row.get(6)?,
+        },
+        SealedKey {
+            kek_id: row.get(7)?,
+            ciphertext: row.get(8)?,
+        },
+    ))
+}
+
+/// Scopes are stored space separated.
+fn token_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<ApiTokenInfo> {
+    Ok(ApiTokenInfo {
+        id: row.get::<_, i64>(offset)? as TokenId,
+        name: row.get(offset + 1)?,
+        scopes: row
+            .get::<_, String>(offset + 2)?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+        created_at: row.get::<_, i64>(offset + 3)? as u64,
+        last_used_at: row.get::<_, Option<i64>>(offset + 4)?.map(|t| t as u64),
+    })
+}
+
+impl SqliteDb {
+    /// Opens (or creates) the database at `path` and brings its schema up to date.
+    pub fn open(path: &str) -> Result<Self, DbError> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        let mut db = Self { conn };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    fn migrate(&mut self) -> Result<(), DbError> {
+        let version: usize = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        if version > MIGRATIONS.len() {
+            return Err(DbError::Storage(format!(
+                "Database schema version {version} is newer than supported {}",
+                MIGRATIONS.len()
+            )));
+        }
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for SqliteDb {
+    fn get_user_password_hash(&self, user: &str) -> Result<(UserId, String), DbError> {
+        self.conn
+            .query_row(
+                "SELECT id, password_hash FROM users WHERE name = ?1",
+                params![user],
+                |row| Ok((row.get::<_, i64>(0)? as UserId, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or(DbError::UserNotFound)
+    }
+
+    fn set_user_password_hash(
+        &mut self,
+        user_id: UserId,
+        password_hash: &str,
+    ) -> Result<(), DbError> {
+        let updated = self.conn.execute(
+            "UPDATE users SET password_hash = ?1 WHERE id = ?2",
+            params![password_hash, user_id as i64],
+        )?;
+        if updated == 0 {
+            return Err(DbError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    fn add_user(
+        &mut self,
+        user: &str,
+        password_hash: &str,
+        invite_hash: Option<&str>,
+    ) -> Result<UserId, DbError> {
+        let tx = self.conn.transaction()?;
+
+        let res = tx.execute(
+            "INSERT INTO users (name, password_hash) VALUES (?1, ?2)",
+            params![user, password_hash],
+        );
+        match res {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                return Err(DbError::UserAlreadyExists);
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let user_id = tx.last_insert_rowid();
+
+        if let Some(invite_hash) = invite_hash {
+            let used = tx.execute(
+                "UPDATE invites SET used_by = ?1, used_at = ?2
+                 WHERE code_hash = ?3 AND used_at IS NULL",
+                params![user_id, unix_time_now() as i64, invite_hash],
+            )?;
+            if used == 0 {
+                return Err(DbError::InviteNotFound);
+            }
+        }
+
+        tx.commit()?;
+        Ok(user_id as UserId)
+    }
+
+    fn add_invite(&mut self, invite_hash: &str) -> Result<(), DbError> {
+        self.conn.execute(
+            "INSERT INTO invites (code_hash, created_at) VALUES (?1, ?2)",
+            params![invite_hash, unix_time_now() as i64],
+        )?;
+        Ok(())
+    }
+
+    fn get_user_key(
+        &self,
+        user_id: UserId,
+        key_id: KeyId,
+    ) -> Result<(KeyInfo, SealedKey), DbError> {
+        self.conn
+            .query_row(
+                "SELECT id, label, algorithm, created_at, exportable, public_id, kek_id, key
+                 FROM keys WHERE id = ?1 AND user_id = ?2",
+                params![key_id as i64, user_id as i64],
+                |row| {
+                    Ok((
+                        KeyInfo {
+                            id: row.get::<_, i64>(0)? as KeyId,
+                            label: row.get(1)?,
+                            algorithm: row.get(2)?,
+                            created_at: row.get::<_, i64>(3)? as u64,
+                            exportable: row.get(4)?,
+                            public_id: row.get(5)?,
+                        },
+                        SealedKey {
+                            kek_id: row.get(6)?,
+                            ciphertext: row.get(7)?,
+                        },
+                    ))
+                },
+            )
+            .optional()?
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    fn get_key(&self, key_id: KeyId) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        self.conn
+            .query_row(
+                "SELECT user_id, id, label, algorithm, created_at, exportable, public_id, kek_id, key
+                 FROM keys WHERE id = ?1",
+                params![key_id as i64],
+                key_from_row,
+            )
+            .optional()?
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    fn get_key_by_public_id(
+        &self,
+        public_id: &str,
+    ) -> Result<(UserId, KeyInfo, SealedKey), DbError> {
+        self.conn
+            .query_row(
+                "SELECT user_id, id, label, algorithm, created_at, exportable, public_id, kek_id, key
+                 FROM keys WHERE public_id = ?1",
+                params![public_id],
+                key_from_row,
+            )
+            .optional()?
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    fn list_user_keys(&self, user_id: UserId) -> Result<Vec<KeyInfo>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, label, algorithm, created_at, exportable, public_id FROM keys
+             WHERE user_id = ?1 ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(params![user_id as i64], |row| {
+            Ok(KeyInfo {
+                id: row.get::<_, i64>(0)? as KeyId,
+                label: row.get(1)?,
+                algorithm: row.get(2)?,
+                created_at: row.get::<_, i64>(3)? as u64,
+                exportable: row.get(4)?,
+                public_id: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn get_user_name(&self, user_id: UserId) -> Option<String> {
+        self.conn
+            .query_row(
+                "SELECT name FROM users WHERE id = ?1",
+                params![user_id as i64],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    fn add_user_key(
+        &mut self,
+        user_id: UserId,
+        label: &str,
+        algorithm: &str,
+        exportable: bool,
+        seal: &dyn Fn(KeyId) -> Result<SealedKey, String>,
+    ) -> Result<KeyId, DbError> {
+        // the row is inserted first to learn the key id the key is sealed for,
+        // the transaction is rolled back if sealing fails
+        let tx = self.conn.transaction()?;
+        let res = tx.execute(
+            "INSERT INTO keys (user_id, label, algorithm, created_at, exportable, public_id, kek_id, key)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, '', x'')",
+            params![
+                user_id as i64,
+                label,
+                algorithm,
+                unix_time_now() as i64,
+                exportable,
+                new_public_id()
+            ],
+        );
+
+        match res {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+            {
+                return Err(DbError::KeyAlreadyExists)
+            }
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY =>
+            {
+                return Err(DbError::UserNotFound)
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let key_id = tx.last_insert_rowid() as KeyId;
+        let key = seal(key_id).map_err(DbError::Storage)?;
+        tx.execute(
+            "UPDATE keys SET kek_id = ?1, key = ?2 WHERE id = ?3",
+            params![key.kek_id, key.ciphertext, key_id as i64],
+        )?;
+        tx.commit()?;
+        Ok(key_id)
+    }
+
+    fn discard_user_key(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError> {
+        let deleted = self.conn.execute(
+            "DELETE FROM keys WHERE id = ?1 AND user_id = ?2",
+            params![key_id as i64, user_id as i64],
+        )?;
+        if deleted == 0 {
+            return Err(DbError::KeyNotFound);
+        }
+        Ok(())
+    }
+
+    fn set_key_non_exportable(&mut self, user_id: UserId, key_id: KeyId) -> Result<(), DbError> {
+        let updated = self.conn.execute(
+            "UPDATE keys SET exportable = 0 WHERE id = ?1 AND user_id = ?2",
+            params![key_id as i64, user_id as i64],
+        )?;
+        if updated == 0 {
+            return Err(DbError::KeyNotFound);
+        }
+        Ok(())
+    }
+
+    fn list_sealed_keys(&self) -> Result<Vec<(UserId, KeyInfo, SealedKey)>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT user_id, id, label, algorithm, created_at, exportable, public_id, kek_id, key
+             FROM keys",
+        )?;
+        let rows = stmt.query_map([], key_from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn update_sealed_key(&mut self, key_id: KeyId, key: &SealedKey) -> Result<(), DbError> {
+        let updated = self.conn.execute(
+            "UPDATE keys SET kek_id = ?1, key = ?2 WHERE id = ?3",
+            params![key.kek_id, key.ciphertext, key_id as i64],
+        )?;
+        if updated == 0 {
+            return Err(DbError::KeyNotFound);
+        }
+        Ok(())
+    }
+
+    fn add_api_token(
+        &mut self,
+        user_id: UserId,
+        name: &str,
+        token_hash: &str,
+        scopes: &[String],
+    ) -> Result<TokenId, DbError> {
+        self.conn.execute(
+            "INSERT INTO api_tokens (user_id, name, token_hash, scopes, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                user_id as i64,
+                name,
+                token_hash,
+                scopes.join(" "),
+                unix_time_now() as i64
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as TokenId)
+    }
+
+    fn list_api_tokens(&self, user_id: UserId) -> Result<Vec<ApiTokenInfo>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, scopes, created_at, last_used_at FROM api_tokens
+             WHERE user_id = ?1 ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(params![user_id as i64], |row| token_from_row(row, 0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn find_api_token(&self, token_hash: &str) -> Result<(UserId, ApiTokenInfo), DbError> {
+        self.conn
+            .query_row(
+                "SELECT user_id, id, name, scopes, created_at, last_used_at FROM api_tokens
+                 WHERE token_hash = ?1",
+                params![token_hash],
+                |row| Ok((row.get::<_, i64>(0)? as UserId, token_from_row(row, 1)?)),
+            )
+            .optional()?
+            .ok_or(DbError::TokenNotFound)
+    }
+
+    fn touch_api_token(&mut self, token_id: TokenId) -> Result<(), DbError> {
+        self.conn.execute(
+            "UPDATE api_tokens SET last_used_at = ?1 WHERE id = ?2",
+            params![unix_time_now() as i64, token_id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn revoke_api_token(&mut self, user_id: UserId, token_id: TokenId) -> Result<(), DbError> {
+        let deleted = self.conn.execute(
+            "DELETE FROM api_tokens WHERE id = ?1 AND user_id = ?2",
+            params![token_id as i64, user_id as i64],
+        )?;
+        if deleted == 0 {
+            return Err(DbError::TokenNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::check_storage;
+    use super::*;
+
+    fn in_memory() -> SqliteDb {
+        SqliteDb::open(":memory:").unwrap()
+    }
+
+    #[test]
+    fn sqlite_storage() {
+        check_storage(&mut in_memory());
+    }
+
+    #[test]
+    fn migrations() {
+        let db = in_memory();
+        let version: usize = db
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        // no accounts anyone could log into
+        assert!(matches!(
+            db.get_user_password_hash("user1"),
+            Err(DbError::UserNotFound)
+        ));
+
+        // already migrated databases are left as they are
+        let mut db = db;
+        db.migrate().unwrap();
+
+        db.conn
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(matches!(db.migrate(), Err(DbError::Storage(_))));
+    }
+
+    #[test]
+    fn migrate_plaintext_key() {
+        // a key stored by the first version is kept as the default key in plaintext
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (id, name, password_hash) VALUES (1, 'alice', 'hash');
+             INSERT INTO keys (user_id, key) VALUES (1, x'0102');
+             PRAGMA user_version = 1;",
+        )
+        .unwrap();
+        let mut db = SqliteDb { conn };
+        db.migrate().unwrap();
+
+        let keys = db.list_sealed_keys().unwrap();
+        assert_eq!(keys.len(), 1);
+        let (user_id, key, sealed) = &keys[0];
+        assert_eq!(
+            (*user_id, key.label.as_str(), key.algorithm.as_str()),
+            (1, "default", "secp256k1")
+        );
+        assert!(key.exportable);
+        assert_eq!(key.public_id.len(), 32);
+        assert_eq!(
+            (sealed.kek_id.as_str(), sealed.ciphertext.as_slice()),
+            ("", [1, 2].as_slice())
+        );
+    }
+}
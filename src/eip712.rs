@@ -0,0 +1,570 @@
+//! Hashing of EIP-712 typed structured data.
+//!
+//! See <https://eips.ethereum.org/EIPS/eip-712>, values are accepted in the
+//! JSON format of `eth_signTypedData_v4`.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha3::{Digest, Keccak256};
+use std::collections::{BTreeMap, BTreeSet};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// Fields of the domain in the order used when the typed data doesn't define
+/// the `EIP712Domain` type.
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TypedField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedField>>,
+    pub primary_type: String,
+    #[serde(default)]
+    pub domain: Map<String, Value>,
+    pub message: Value,
+}
+
+/// A field of the typed data prepared for display, structs and arrays are
+/// followed by their members with a greater depth.
+#[derive(Clone, Debug)]
+pub struct FieldView {
+    pub depth: usize,
+    pub name: String,
+    pub type_name: String,
+    /// Value of an atomic field, `None` for structs and arrays.
+    pub value: Option<String>,
+}
+
+impl TypedData {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let typed_data: Self = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if typed_data.primary_type == DOMAIN_TYPE
+            || !typed_data.types.contains_key(&typed_data.primary_type)
+        {
+            return Err(format!(
+                "Primary type '{}' is not defined",
+                typed_data.primary_type
+            ));
+        }
+        Ok(typed_data)
+    }
+
+    /// `hashStruct` of the domain.
+    pub fn domain_separator(&self) -> Result<[u8; 32], String> {
+        self.hash_struct(DOMAIN_TYPE, &Value::Object(self.domain.clone()))
+    }
+
+    /// `hashStruct` of the message.
+    pub fn struct_hash(&self) -> Result<[u8; 32], String> {
+        self.hash_struct(&self.primary_type, &self.message)
+    }
+
+    /// Digest which is signed: `keccak256(0x1901 || domainSeparator || hashStruct(message))`.
+    pub fn signing_hash(&self) -> Result<[u8; 32], String> {
+        let mut hasher = Keccak256::new();
+        hasher.update([0x19, 0x01]);
+        hasher.update(self.domain_separator()?);
+        hasher.update(self.struct_hash()?);
+        Ok(hasher.finalize().into())
+    }
+
+    /// Fields of the domain, as displayed to the user before signing.
+    pub fn domain_view(&self) -> Result<Vec<FieldView>, String> {
+        let mut view = Vec::new();
+        self.view_struct(
+            DOMAIN_TYPE,
+            &Value::Object(self.domain.clone()),
+            0,
+            &mut view,
+        )?;
+        Ok(view)
+    }
+
+    /// Fields of the message, as displayed to the user before signing.
+    pub fn message_view(&self) -> Result<Vec<FieldView>, String> {
+        let mut view = Vec::new();
+        self.view_struct(&self.primary_type, &self.message, 0, &mut view)?;
+        Ok(view)
+    }
+
+    fn fields(&self, type_name: &str) -> Result<Vec<TypedField>, String> {
+        if let Some(fields) = self.types.get(type_name) {
+            return Ok(fields.clone());
+        }
+        if type_name == DOMAIN_TYPE {
+            return Ok(DOMAIN_FIELDS
+                .iter()
+                .filter(|(name, _)| self.domain.contains_key(*name))
+                .map(|(name, type_name)| TypedField {
+                    name: name.to_string(),
+                    type_name: type_name.to_string(),
+                })
+                .collect());
+        }
+        Err(format!("Type '{type_name}' is not defined"))
+    }
+
+    fn is_struct(&self, type_name: &str) -> bool {
+        self.types.contains_key(type_name) || type_name == DOMAIN_TYPE
+    }
+
+    /// Struct types referenced by the type, including itself.
+    fn collect_dependencies(
+        &self,
+        type_name: &str,
+        found: &mut BTreeSet<String>,
+    ) -> Result<(), String> {
+        if !self.is_struct(type_name) || !found.insert(type_name.to_string()) {
+            return Ok(());
+        }
+        for field in self.fields(type_name)? {
+            self.collect_dependencies(base_type(&field.type_name), found)?;
+        }
+        Ok(())
+    }
+
+    /// `encodeType`: the type followed by its dependencies sorted by name.
+    fn encode_type(&self, type_name: &str) -> Result<String, String> {
+        let mut dependencies = BTreeSet::new();
+        self.collect_dependencies(type_name, &mut dependencies)?;
+        dependencies.remove(type_name);
+
+        let mut encoded = String::new();
+        for name in std::iter::once(type_name).chain(dependencies.iter().map(|s| s.as_str())) {
+            let fields: Vec<String> = self
+                .fields(name)?
+                .iter()
+                .map(|field| format!("{} {}", field.type_name, field.name))
+                .collect();
+            encoded.push_str(&format!("{name}({})", fields.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn hash_struct(&self, type_name: &str, value: &Value) -> Result<[u8; 32], String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("Value of '{type_name}' must be an object"))?;
+
+        let mut hasher = Keccak256::new();
+        hasher.update(Keccak256::digest(self.encode_type(type_name)?.as_bytes()));
+        for field in self.fields(type_name)? {
+            let value = object
+                .get(&field.name)
+                .ok_or_else(|| format!("Missing field '{}' of '{type_name}'", field.name))?;
+            hasher.update(
+                self.encode_value(&field.type_name, value)
+                    .map_err(|e| format!("{}: {e}", field.name))?,
+            );
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Encodes a member value into a 32 byte word, dynamic values are hashed.
+    fn encode_value(&self, type_name: &str, value: &Value) -> Result<[u8; 32], String> {
+        if let Some((element_type, len)) = array_type(type_name)? {
+            let items = array_items(type_name, len, value)?;
+            let mut hasher = Keccak256::new();
+            for item in items {
+                hasher.update(self.encode_value(element_type, item)?);
+            }
+            return Ok(hasher.finalize().into());
+        }
+        if self.is_struct(type_name) {
+            return self.hash_struct(type_name, value);
+        }
+
+        encode_atomic(type_name, value)
+    }
+
+    fn view_struct(
+        &self,
+        type_name: &str,
+        value: &Value,
+        depth: usize,
+        view: &mut Vec<FieldView>,
+    ) -> Result<(), String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("Value of '{type_name}' must be an object"))?;
+        for field in self.fields(type_name)? {
+            let value = object
+                .get(&field.name)
+                .ok_or_else(|| format!("Missing field '{}' of '{type_name}'", field.name))?;
+            self.view_value(&field.name, &field.type_name, value, depth, view)?;
+        }
+        Ok(())
+    }
+
+    fn view_value(
+        &self,
+        name: &str,
+        type_name: &str,
+        value: &Value,
+        depth: usize,
+        view: &mut Vec<FieldView>,
+    ) -> Result<(), String> {
+        let atomic = array_type(type_name)?.is_none() && !self.is_struct(type_name);
+        view.push(FieldView {
+            depth,
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            value: if atomic {
+                Some(display_atomic(type_name, value)?)
+            } else {
+                None
+            },
+        });
+
+        if let Some((element_type, len)) = array_type(type_name)? {
+            let items = array_items(type_name, len, value)?;
+            for (i, item) in items.iter().enumerate() {
+                self.view_value(&format!("[{i}]"), element_type, item, depth + 1, view)?;
+            }
+        } else if !atomic {
+            self.view_struct(type_name, value, depth + 1, view)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of the type without array suffixes, e.g. `Person` for `Person[][2]`.
+fn base_type(type_name: &str) -> &str {
+    type_name.split('[').next().unwrap_or(type_name)
+}
+
+/// Splits `T[]` or `T[n]` into the element type and the fixed length.
+fn array_type(type_name: &str) -> Result<Option<(&str, Option<usize>)>, String> {
+    let Some(inner) = type_name.strip_suffix(']') else {
+        return Ok(None);
+    };
+    let open = inner
+        .rfind('[')
+        .ok_or_else(|| format!("Invalid type '{type_name}'"))?;
+    let len = match &inner[open + 1..] {
+        "" => None,
+        len => Some(
+            len.parse()
+                .map_err(|_| format!("Invalid array length in '{type_name}'"))?,
+        ),
+    };
+    Ok(Some((&inner[..open], len)))
+}
+
+fn array_items<'a>(
+    type_name: &str,
+    len: Option<usize>,
+    value: &'a Value,
+) -> Result<&'a Vec<Value>, String> {
+    let items = value
+        .as_array()
+        .ok_or_else(|| format!("Value of '{type_name}' must be an array"))?;
+    if len.is_some_and(|len| len != items.len()) {
+        return Err(format!("Value of '{type_name}' has {} items", items.len()));
+    }
+    Ok(items)
+}
+
+/// Bit size of `uintN`/`intN` or byte size of `bytesN`, `default` for the bare name.
+fn type_size(suffix: &str, default: usize) -> Option<usize> {
+    if suffix.is_empty() {
+        Some(default)
+    } else {
+        suffix.parse().ok()
+    }
+}
+
+fn encode_atomic(type_name: &str, value: &Value) -> Result<[u8; 32], String> {
+    let invalid = || format!("Invalid value of '{type_name}': {value}");
+    let mut word = [0u8; 32];
+
+    match type_name {
+        "string" => {
+            let s = value.as_str().ok_or_else(invalid)?;
+            return Ok(Keccak256::digest(s.as_bytes()).into());
+        }
+        "bytes" => {
+            let bytes = parse_hex(value).ok_or_else(invalid)?;
+            return Ok(Keccak256::digest(bytes).into());
+        }
+        "bool" => {
+            word[31] = value.as_bool().ok_or_else(invalid)? as u8;
+            return Ok(word);
+        }
+        "address" => {
+            let bytes = parse_hex(value)
+                .filter(|bytes| bytes.len() == 20)
+                .ok_or_else(invalid)?;
+            word[12..].copy_from_slice(&bytes);
+            return Ok(word);
+        }
+        _ => {}
+    }
+
+    if let Some(size) = type_name.strip_prefix("bytes") {
+        let size = type_size(size, 0)
+            .filter(|size| (1..=32).contains(size))
+            .ok_or_else(|| format!("Unknown type '{type_name}'"))?;
+        let bytes = parse_hex(value)
+            .filter(|bytes| bytes.len() == size)
+            .ok_or_else(invalid)?;
+        word[..size].copy_from_slice(&bytes);
+        return Ok(word);
+    }
+
+    let (signed, bits) = if let Some(bits) = type_name.strip_prefix("uint") {
+        (false, bits)
+    } else if let Some(bits) = type_name.strip_prefix("int") {
+        (true, bits)
+    } else {
+        return Err(format!("Unknown type '{type_name}'"));
+    };
+    let bits = type_size(bits, 256)
+        .filter(|bits| bits % 8 == 0 && (8..=256).contains(bits))
+        .ok_or_else(|| format!("Unknown type '{type_name}'"))?;
+
+    let number = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.trim().to_string(),
+        _ => return Err(invalid()),
+    };
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) if signed => (true, digits),
+        _ => (false, number.as_str()),
+    };
+    let magnitude = parse_uint256(digits).ok_or_else(invalid)?;
+
+    if !negative {
+        let max_bits = if signed { bits - 1 } else { bits };
+        if bit_len(&magnitude) > max_bits {
+            return Err(invalid());
+        }
+        return Ok(magnitude);
+    }
+    if magnitude == [0u8; 32] {
+        return Ok(magnitude);
+    }
+    // two's complement: -m == !(m - 1), the range allows m - 1 < 2^(bits - 1)
+    let mut decremented = magnitude;
+    for byte in decremented.iter_mut().rev() {
+        let (result, borrow) = byte.overflowing_sub(1);
+        *byte = result;
+        if !borrow {
+            break;
+        }
+    }
+    if bit_len(&decremented) > bits - 1 {
+        return Err(invalid());
+    }
+    Ok(decremented.map(|byte| !byte))
+}
+
+/// Human-readable form of an atomic value, checked the same way as for hashing.
+fn display_atomic(type_name: &str, value: &Value) -> Result<String, String> {
+    encode_atomic(type_name, value)?;
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    })
+}
+
+fn parse_hex(value: &Value) -> Option<Vec<u8>> {
+    hex::decode(value.as_str()?.trim().strip_prefix("0x")?).ok()
+}
+
+/// Parses a non-negative decimal or `0x` hex integer into a big-endian word.
+pub(crate) fn parse_uint256(s: &str) -> Option<[u8; 32]> {
+    if let Some(digits) = s.strip_prefix("0x") {
+        if digits.is_empty() || digits.len() > 64 {
+            return None;
+        }
+        return hex::decode(format!("{digits:0>64}")).ok()?.try_into().ok();
+    }
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut word = [0u8; 32];
+    for digit in s.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in word.iter_mut().rev() {
+            let v = *byte as u32 * 10 + carry;
+            *byte = v as u8;
+            carry = v >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+    Some(word)
+}
+
+fn bit_len(word: &[u8; 32]) -> usize {
+    match word.iter().position(|&byte| byte != 0) {
+        Some(i) => (32 - i) * 8 - word[i].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Mail` example of the specification.
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    /// `eth_signTypedData_v4` example with arrays of structs and addresses.
+    const MAIL_ARRAYS: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Group": [
+                {"name": "name", "type": "string"},
+                {"name": "members", "type": "Person[]"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person[]"},
+                {"name": "contents", "type": "string"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallets", "type": "address[]"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {
+                "name": "Cow",
+                "wallets": [
+                    "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+                    "0xDeaDbeefdEAdbeefdEadbEEFdeadbeEFdEaDbeeF"
+                ]
+            },
+            "to": [{
+                "name": "Bob",
+                "wallets": [
+                    "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB",
+                    "0xB0BdaBea57B0BDABeA57b0bdABEA57b0BDabEa57",
+                    "0xB0B0b0b0b0b0B000000000000000000000000000"
+                ]
+            }],
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    fn type_hash(typed_data: &TypedData, type_name: &str) -> String {
+        hex::encode(Keccak256::digest(
+            typed_data.encode_type(type_name).unwrap().as_bytes(),
+        ))
+    }
+
+    #[test]
+    fn mail() {
+        let typed_data = TypedData::parse(MAIL).unwrap();
+
+        assert_eq!(
+            typed_data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            type_hash(&typed_data, "Mail"),
+            "a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2"
+        );
+        assert_eq!(
+            hex::encode(typed_data.struct_hash().unwrap()),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            hex::encode(typed_data.domain_separator().unwrap()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            hex::encode(typed_data.signing_hash().unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+
+    #[test]
+    fn mail_with_arrays() {
+        let typed_data = TypedData::parse(MAIL_ARRAYS).unwrap();
+
+        assert_eq!(
+            typed_data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person[] to,string contents)Person(string name,address[] wallets)"
+        );
+        assert_eq!(
+            typed_data.encode_type("Group").unwrap(),
+            "Group(string name,Person[] members)Person(string name,address[] wallets)"
+        );
+        assert_eq!(
+            hex::encode(typed_data.domain_separator().unwrap()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            hex::encode(typed_data.signing_hash().unwrap()),
+            "a85c2e2b118698e88db68a8105b794a8cc7cec074e89ef991cb4f5f533819cc2"
+        );
+    }
+
+    #[test]
+    fn missing_field() {
+        let typed_data =
+            TypedData::parse(&MAIL.replace(r#""contents": "Hello, Bob!""#, r#""other": 1"#))
+                .unwrap();
+        assert!(typed_data.struct_hash().is_err());
+    }
+}
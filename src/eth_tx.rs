@@ -0,0 +1,511 @@
+//! Ethereum transactions: legacy with EIP-155 replay protection, EIP-2930
+//! access list and EIP-1559 dynamic fee envelopes.
+//!
+//! Transactions are given as JSON with the field names of `eth_sendTransaction`,
+//! quantities as numbers, decimal strings or `0x` hex strings.
+
+use serde::Deserialize;
+use serde_json::Value;
+use sha3::{Digest, Keccak256};
+
+use super::eip712::parse_uint256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxType {
+    Legacy,
+    /// EIP-2930, type 1.
+    AccessList,
+    /// EIP-1559, type 2.
+    DynamicFee,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TransactionJson {
+    #[serde(rename = "type")]
+    tx_type: Option<Value>,
+    from: Option<String>,
+    chain_id: Value,
+    nonce: Value,
+    gas_price: Option<Value>,
+    max_priority_fee_per_gas: Option<Value>,
+    max_fee_per_gas: Option<Value>,
+    #[serde(alias = "gasLimit")]
+    gas: Value,
+    to: Option<String>,
+    value: Option<Value>,
+    #[serde(alias = "input")]
+    data: Option<String>,
+    access_list: Option<Vec<AccessListItemJson>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct AccessListItemJson {
+    address: String,
+    storage_keys: Vec<String>,
+}
+
+/// Unsigned transaction, quantities are kept as minimal big-endian bytes.
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub tx_type: TxType,
+    pub chain_id: u64,
+    /// Sender, not part of the signed transaction but it has to be the
+    /// address of the signing key when given.
+    pub from: Option<[u8; 20]>,
+    nonce: Vec<u8>,
+    gas_price: Vec<u8>,
+    max_priority_fee_per_gas: Vec<u8>,
+    max_fee_per_gas: Vec<u8>,
+    gas: Vec<u8>,
+    /// `None` creates a contract.
+    to: Option<[u8; 20]>,
+    value: Vec<u8>,
+    data: Vec<u8>,
+    access_list: Vec<([u8; 20], Vec<[u8; 32]>)>,
+}
+
+impl Transaction {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let tx: TransactionJson = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let tx_type = match &tx.tx_type {
+            Some(tx_type) => match parse_quantity("type", tx_type)?.as_slice() {
+                [] => TxType::Legacy,
+                [1] => TxType::AccessList,
+                [2] => TxType::DynamicFee,
+                _ => return Err(format!("Unsupported transaction type {tx_type}")),
+            },
+            None if tx.max_fee_per_gas.is_some() => TxType::DynamicFee,
+            None if tx.access_list.is_some() => TxType::AccessList,
+            None => TxType::Legacy,
+        };
+
+        let chain_id = parse_quantity("chainId", &tx.chain_id)?;
+        // the legacy v = chainId * 2 + 36 has to fit as well
+        if chain_id.is_empty() || chain_id.len() > 7 {
+            return Err("chainId must be between 1 and 2^56".to_string());
+        }
+        let chain_id = chain_id.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+
+        let required = |name: &str, value: &Option<Value>| match value {
+            Some(value) => parse_quantity(name, value),
+            None => Err(format!("Missing {name}")),
+        };
+        let forbidden = |name: &str, present: bool| {
+            if present {
+                Err(format!("{name} is not allowed in this transaction type"))
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let (gas_price, max_priority_fee_per_gas, max_fee_per_gas) = match tx_type {
+            TxType::Legacy | TxType::AccessList => (
+                required("gasPrice", &tx.gas_price)?,
+                forbidden(
+                    "maxPriorityFeePerGas",
+                    tx.max_priority_fee_per_gas.is_some(),
+                )?,
+                forbidden("maxFeePerGas", tx.max_fee_per_gas.is_some())?,
+            ),
+            TxType::DynamicFee => (
+                forbidden("gasPrice", tx.gas_price.is_some())?,
+                required("maxPriorityFeePerGas", &tx.max_priority_fee_per_gas)?,
+                required("maxFeePerGas", &tx.max_fee_per_gas)?,
+            ),
+        };
+        if tx_type == TxType::Legacy && tx.access_list.is_some() {
+            return Err("accessList is not allowed in a legacy transaction".to_string());
+        }
+        // both are minimal big-endian, a longer one is the greater one
+        if (max_priority_fee_per_gas.len(), &max_priority_fee_per_gas)
+            > (max_fee_per_gas.len(), &max_fee_per_gas)
+        {
+            return Err("maxPriorityFeePerGas must not exceed maxFeePerGas".to_string());
+        }
+
+        let from = match tx.from.as_deref() {
+            None | Some("") => None,
+            Some(from) => {
+                Some(parse_fixed_hex(from).ok_or_else(|| format!("Invalid from: {from}"))?)
+            }
+        };
+        let to = match tx.to.as_deref() {
+            None | Some("") => None,
+            Some(to) => Some(parse_fixed_hex(to).ok_or_else(|| format!("Invalid to: {to}"))?),
+        };
+        let data = match tx.data.as_deref() {
+            None | Some("") | Some("0x") => Vec::new(),
+            Some(data) => parse_hex(data).ok_or_else(|| "Invalid data".to_string())?,
+        };
+        let access_list = tx
+            .access_list
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| {
+                let address = parse_fixed_hex(&item.address)
+                    .ok_or_else(|| format!("Invalid access list address: {}", item.address))?;
+                let keys = item
+                    .storage_keys
+                    .iter()
+                    .map(|key| {
+                        parse_fixed_hex(key).ok_or_else(|| format!("Invalid storage key: {key}"))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok((address, keys))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            tx_type,
+            chain_id,
+            from,
+            nonce: parse_quantity("nonce", &tx.nonce)?,
+            gas_price,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas: parse_quantity("gas", &tx.gas)?,
+            to,
+            value: match &tx.value {
+                Some(value) => parse_quantity("value", value)?,
+                None => Vec::new(),
+            },
+            data,
+            access_list,
+        })
+    }
+
+    /// Fields shared by the signing payload and the signed transaction.
+    fn fields(&self) -> Vec<Vec<u8>> {
+        let to = rlp_bytes(self.to.as_ref().map(|to| to.as_slice()).unwrap_or_default());
+        let access_list = rlp_list(
+            &self
+                .access_list
+                .iter()
+                .map(|(address, keys)| {
+                    let keys: Vec<Vec<u8>> = keys.iter().map(|key| rlp_bytes(key)).collect();
+                    rlp_list(&[rlp_bytes(address), rlp_list(&keys)])
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        match self.tx_type {
+            TxType::Legacy => vec![
+                rlp_bytes(&self.nonce),
+                rlp_bytes(&self.gas_price),
+                rlp_bytes(&self.gas),
+                to,
+                rlp_bytes(&self.value),
+                rlp_bytes(&self.data),
+            ],
+            TxType::AccessList => vec![
+                rlp_bytes(&minimal_bytes(&self.chain_id.to_be_bytes())),
+                rlp_bytes(&self.nonce),
+                rlp_bytes(&self.gas_price),
+                rlp_bytes(&self.gas),
+                to,
+                rlp_bytes(&self.value),
+                rlp_bytes(&self.data),
+                access_list,
+            ],
+            TxType::DynamicFee => vec![
+                rlp_bytes(&minimal_bytes(&self.chain_id.to_be_bytes())),
+                rlp_bytes(&self.nonce),
+                rlp_bytes(&self.max_priority_fee_per_gas),
+                rlp_bytes(&self.max_fee_per_gas),
+                rlp_bytes(&self.gas),
+                to,
+                rlp_bytes(&self.value),
+                rlp_bytes(&self.data),
+                access_list,
+            ],
+        }
+    }
+
+    /// Prepends the envelope type byte of typed transactions (EIP-2718).
+    fn envelope(&self, payload: Vec<u8>) -> Vec<u8> {
+        match self.tx_type {
+            TxType::Legacy => payload,
+            TxType::AccessList => [vec![0x01], payload].concat(),
+            TxType::DynamicFee => [vec![0x02], payload].concat(),
+        }
+    }
+
+    /// Encoded transaction which is hashed for signing, legacy transactions
+    /// commit to the chain id as of EIP-155.
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut fields = self.fields();
+        if self.tx_type == TxType::Legacy {
+            fields.push(rlp_bytes(&minimal_bytes(&self.chain_id.to_be_bytes())));
+            fields.push(rlp_bytes(&[]));
+            fields.push(rlp_bytes(&[]));
+        }
+        self.envelope(rlp_list(&fields))
+    }
+
+    /// Hash which is signed.
+    pub fn signing_hash(&self) -> [u8; 32] {
+        Keccak256::digest(self.signing_payload()).into()
+    }
+
+    /// Raw signed transaction as accepted by `eth_sendRawTransaction`.
+    pub fn encode_signed(&self, recovery_id: u8, r: &[u8], s: &[u8]) -> Vec<u8> {
+        let v = match self.tx_type {
+            TxType::Legacy => self.chain_id as u128 * 2 + 35 + recovery_id as u128,
+            TxType::AccessList | TxType::DynamicFee => recovery_id as u128,
+        };
+
+        let mut fields = self.fields();
+        fields.push(rlp_bytes(&minimal_bytes(&v.to_be_bytes())));
+        fields.push(rlp_bytes(&minimal_bytes(r)));
+        fields.push(rlp_bytes(&minimal_bytes(s)));
+        self.envelope(rlp_list(&fields))
+    }
+
+    /// Extracts the recovery id and the 64 byte r||s signature of a raw
+    /// signed transaction.
+    pub fn decode_signature(&self, raw: &[u8]) -> Option<(u8, [u8; 64])> {
+        let payload = match self.tx_type {
+            TxType::Legacy => raw,
+            TxType::AccessList => raw.strip_prefix(&[0x01])?,
+            TxType::DynamicFee => raw.strip_prefix(&[0x02])?,
+        };
+        let items = rlp_decode_list(payload)?;
+        let [v, r, s] = items.get(items.len().checked_sub(3)?..)? else {
+            return None;
+        };
+        if v.len() > 16 || r.len() > 32 || s.len() > 32 {
+            return None;
+        }
+
+        let v = v.iter().fold(0u128, |acc, b| acc << 8 | *b as u128);
+        let recovery_id = match self.tx_type {
+            TxType::Legacy => v.checked_sub(self.chain_id as u128 * 2 + 35)?,
+            TxType::AccessList | TxType::DynamicFee => v,
+        };
+        if recovery_id > 1 {
+            return None;
+        }
+
+        let mut signature = [0u8; 64];
+        signature[32 - r.len()..32].copy_from_slice(r);
+        signature[64 - s.len()..].copy_from_slice(s);
+        Some((recovery_id as u8, signature))
+    }
+}
+
+/// Parses a non-negative quantity up to 2^256 - 1 into minimal big-endian bytes.
+fn parse_quantity(name: &str, value: &Value) -> Result<Vec<u8>, String> {
+    let number = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.trim().to_string(),
+        _ => String::new(),
+    };
+    parse_uint256(&number)
+        .map(|word| minimal_bytes(&word))
+        .ok_or_else(|| format!("Invalid {name}: {value}"))
+}
+
+fn parse_hex(input: &str) -> Option<Vec<u8>> {
+    hex::decode(input.trim().strip_prefix("0x")?).ok()
+}
+
+fn parse_fixed_hex<const N: usize>(input: &str) -> Option<[u8; N]> {
+    parse_hex(input)?.try_into().ok()
+}
+
+/// Strips leading zero bytes, RLP encodes integers without them.
+fn minimal_bytes(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+fn rlp_length_prefix(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![offset + len as u8]
+    } else {
+        let len_bytes = minimal_bytes(&len.to_be_bytes());
+        [vec![offset + 55 + len_bytes.len() as u8], len_bytes].concat()
+    }
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    match bytes {
+        [b] if *b < 0x80 => vec![*b],
+        _ => [rlp_length_prefix(bytes.len(), 0x80), bytes.to_vec()].concat(),
+    }
+}
+
+/// RLP list of already encoded items.
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    [rlp_length_prefix(payload.len(), 0xc0), payload].concat()
+}
+
+/// Splits an RLP item into whether it is a list, its payload and the rest of the input.
+fn rlp_decode_item(input: &[u8]) -> Option<(bool, &[u8], &[u8])> {
+    let (&prefix, rest) = input.split_first()?;
+    let (is_list, header_len, len) = match prefix {
+        0x00..=0x7f => return Some((false, &input[..1], rest)),
+        0x80..=0xb7 => (false, 0, (prefix - 0x80) as usize),
+        0xb8..=0xbf => (false, (prefix - 0xb7) as usize, 0),
+        0xc0..=0xf7 => (true, 0, (prefix - 0xc0) as usize),
+        0xf8..=0xff => (true, (prefix - 0xf7) as usize, 0),
+    };
+    let len = if header_len > 0 {
+        let len_bytes = rest.get(..header_len)?;
+        if len_bytes.len() > 8 {
+            return None;
+        }
+        len_bytes
+            .iter()
+            .fold(0usize, |acc, b| acc << 8 | *b as usize)
+    } else {
+        len
+    };
+    let rest = &rest[header_len..];
+    let payload = rest.get(..len)?;
+    Some((is_list, payload, &rest[len..]))
+}
+
+/// Payloads of the items of a top-level RLP list which must span the whole input.
+fn rlp_decode_list(input: &[u8]) -> Option<Vec<&[u8]>> {
+    let (is_list, mut payload, rest) = rlp_decode_item(input)?;
+    if !is_list || !rest.is_empty() {
+        return None;
+    }
+
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let (_, item, rest) = rlp_decode_item(payload)?;
+        items.push(item);
+        payload = rest;
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    /// Private key of the EIP-155 example.
+    const KEY: [u8; 32] = [0x46; 32];
+
+    struct Vector {
+        tx: &'static str,
+        payload: &'static str,
+        hash: &'static str,
+        raw: &'static str,
+    }
+
+    /// Legacy transaction from the EIP-155 specification.
+    const EIP155: Vector = Vector {
+        tx: r#"{"nonce": 9, "gasPrice": "20000000000", "gas": 21000, "chainId": 1,
+            "to": "0x3535353535353535353535353535353535353535", "value": "1000000000000000000"}"#,
+        payload: "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080",
+        hash: "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53",
+        raw: "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+    };
+
+    const ACCESS_LIST: Vector = Vector {
+        tx: r#"{"type": "0x1", "nonce": "0x9", "gasPrice": "0x4a817c800", "gas": "0x7530", "chainId": "0x1",
+            "to": "0x3535353535353535353535353535353535353535", "value": "0xde0b6b3a7640000",
+            "accessList": [{"address": "0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae", "storageKeys": [
+                "0x0000000000000000000000000000000000000000000000000000000000000003",
+                "0x0000000000000000000000000000000000000000000000000000000000000007"]}]}"#,
+        payload: "01f88701098504a817c800827530943535353535353535353535353535353535353535880de0b6b3a764000080f85bf85994de0b295669a9fd93d5f28d9ec85e40f4cb697baef842a00000000000000000000000000000000000000000000000000000000000000003a00000000000000000000000000000000000000000000000000000000000000007",
+        hash: "01a2edaf0965be86f04c30baa9dfbf917a80a9847d81780e184ff03eb2b296b7",
+        raw: "01f8ca01098504a817c800827530943535353535353535353535353535353535353535880de0b6b3a764000080f85bf85994de0b295669a9fd93d5f28d9ec85e40f4cb697baef842a00000000000000000000000000000000000000000000000000000000000000003a0000000000000000000000000000000000000000000000000000000000000000701a0e0c5b0931ee7a7413de2a7829e9b9b10af640444733eccc816a94daad2b9d2d4a06f701e25cee5d2fa5802b4204cabd6b8adaa89695ab2145ce054c6340bc3e5cb",
+    };
+
+    const DYNAMIC_FEE: Vector = Vector {
+        tx: r#"{"type": 2, "nonce": 9, "maxPriorityFeePerGas": "2000000000", "maxFeePerGas": "30000000000",
+            "gas": 21000, "chainId": 1, "to": "0x3535353535353535353535353535353535353535",
+            "value": "1000000000000000000", "data": "0xa9059cbb"}"#,
+        payload: "02f4010984773594008506fc23ac00825208943535353535353535353535353535353535353535880de0b6b3a764000084a9059cbbc0",
+        hash: "6046ce4b21d6b264bb6919409f0ccd48ed712fa2b575b07f3dc3a2f7ce13c25b",
+        raw: "02f877010984773594008506fc23ac00825208943535353535353535353535353535353535353535880de0b6b3a764000084a9059cbbc080a04ee3782152264e534ac3d8423ae06b7685b4adfb2db05410de9c130e27dd1080a027027c9cb43374ec31bfdb6c91794347ed7c91831a865fb484b61298cad32266",
+    };
+
+    fn check(vector: &Vector, tx_type: TxType) {
+        let tx = Transaction::parse(vector.tx).unwrap();
+        assert_eq!(tx.tx_type, tx_type);
+        assert_eq!(hex::encode(tx.signing_payload()), vector.payload);
+        assert_eq!(hex::encode(tx.signing_hash()), vector.hash);
+
+        // RFC 6979 nonces make the signature deterministic
+        let signing_key = SigningKey::from_slice(&KEY).unwrap();
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(&tx.signing_hash())
+            .unwrap();
+        assert!(signature.normalize_s().is_none());
+        let (r, s) = signature.split_bytes();
+        let raw = tx.encode_signed(recovery_id.to_byte(), &r, &s);
+        assert_eq!(hex::encode(&raw), vector.raw);
+
+        let (decoded_id, decoded) = tx.decode_signature(&raw).unwrap();
+        assert_eq!(decoded_id, recovery_id.to_byte());
+        assert_eq!(decoded, signature.to_bytes().as_slice());
+        assert_eq!(
+            tx.encode_signed(decoded_id, &decoded[..32], &decoded[32..]),
+            raw
+        );
+    }
+
+    #[test]
+    fn legacy_eip155() {
+        check(&EIP155, TxType::Legacy);
+    }
+
+    #[test]
+    fn access_list() {
+        check(&ACCESS_LIST, TxType::AccessList);
+    }
+
+    #[test]
+    fn dynamic_fee() {
+        check(&DYNAMIC_FEE, TxType::DynamicFee);
+    }
+
+    #[test]
+    fn from_is_not_signed() {
+        let tx = Transaction::parse(&EIP155.tx.replace(
+            r#""nonce""#,
+            r#""from": "0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F", "nonce""#,
+        ))
+        .unwrap();
+        assert_eq!(
+            tx.from.map(hex::encode).as_deref(),
+            Some("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f")
+        );
+        assert_eq!(hex::encode(tx.signing_hash()), EIP155.hash);
+
+        assert!(Transaction::parse(
+            &EIP155
+                .tx
+                .replace(r#""nonce""#, r#""from": "0x12", "nonce""#)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rlp_round_trip() {
+        let long = vec![0xab; 60];
+        let encoded = rlp_list(&[
+            rlp_bytes(&[]),
+            rlp_bytes(&[0x7f]),
+            rlp_bytes(&[0x80]),
+            rlp_bytes(&long),
+        ]);
+        assert_eq!(
+            rlp_decode_list(&encoded).unwrap(),
+            vec![&[][..], &[0x7f], &[0x80], &long]
+        );
+
+        // trailing bytes and truncated items are rejected
+        assert!(rlp_decode_list(&[encoded.as_slice(), &[0x00]].concat()).is_none());
+        assert!(rlp_decode_list(&encoded[..encoded.len() - 1]).is_none());
+    }
+}
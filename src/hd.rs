@@ -0,0 +1,243 @@
+//! Hierarchical deterministic keys (BIP-32) derived from BIP-39 mnemonics.
+
+use bip39::Mnemonic;
+use hmac::{Hmac, Mac};
+use k256::ecdsa::SigningKey;
+use k256::elliptic_curve::PrimeField;
+use k256::{FieldBytes, Scalar};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha512;
+use zeroize::Zeroizing;
+
+/// Offset of hardened child indexes, written as `'` or `h` in paths.
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// Child indexes from the master key, e.g. `m/44'/60'/0'/0/0`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parts = input.trim().split('/');
+        if parts.next() != Some("m") {
+            return Err("path has to start with m".to_string());
+        }
+
+        parts
+            .map(|part| {
+                let (index, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+                    Some(index) => (index, true),
+                    None => (part, false),
+                };
+                let index: u32 = index
+                    .parse()
+                    .ok()
+                    .filter(|index| *index < HARDENED)
+                    .ok_or(format!("invalid child index '{part}'"))?;
+                Ok(if hardened { index + HARDENED } else { index })
+            })
+            .collect::<Result<_, _>>()
+            .map(DerivationPath)
+    }
+
+    pub fn child(&self, index: u32) -> Self {
+        DerivationPath([self.0.as_slice(), &[index]].concat())
+    }
+}
+
+impl std::fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            if *index >= HARDENED {
+                write!(f, "/{}'", index - HARDENED)?;
+            } else {
+                write!(f, "/{index}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Private key together with its chain code.
+pub struct ExtendedKey {
+    key: SigningKey,
+    chain_code: Zeroizing<[u8; 32]>,
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> Zeroizing<[u8; 64]> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+    for data in data {
+        mac.update(data);
+    }
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+impl ExtendedKey {
+    /// Master key of a seed, e.g. the 64 bytes of a BIP-39 mnemonic.
+    pub fn master(seed: &[u8]) -> Result<Self, String> {
+        let hash = hmac_sha512(b"Bitcoin seed", &[seed]);
+        Ok(ExtendedKey {
+            key: SigningKey::from_slice(&hash[..32])
+                .map_err(|_| "seed doesn't give a valid master key".to_string())?,
+            chain_code: Zeroizing::new(hash[32..].try_into().expect("64 byte hash")),
+        })
+    }
+
+    fn child(&self, index: u32) -> Result<Self, String> {
+        let hash = if index >= HARDENED {
+            hmac_sha512(
+                self.chain_code.as_slice(),
+                &[&[0], &self.key.to_bytes(), &index.to_be_bytes()],
+            )
+        } else {
+            let public_key = self.key.verifying_key().to_encoded_point(true);
+            hmac_sha512(
+                self.chain_code.as_slice(),
+                &[public_key.as_bytes(), &index.to_be_bytes()],
+            )
+        };
+
+        // Both cases have a probability below 2^-127, BIP-32 skips such indexes.
+        let tweak: Option<Scalar> = Scalar::from_repr(*FieldBytes::from_slice(&hash[..32])).into();
+        let tweak = tweak.ok_or(format!("child {index} is invalid"))?;
+        let key =
+            SigningKey::from_bytes(&(tweak + self.key.as_nonzero_scalar().as_ref()).to_bytes())
+                .map_err(|_| format!("child {index} is invalid"))?;
+
+        Ok(ExtendedKey {
+            key,
+            chain_code: Zeroizing::new(hash[32..].try_into().expect("64 byte hash")),
+        })
+    }
+
+    pub fn derive(&self, path: &DerivationPath) -> Result<Self, String> {
+        let mut key = ExtendedKey {
+            key: self.key.clone(),
+            chain_code: self.chain_code.clone(),
+        };
+        for index in &path.0 {
+            key = key.child(*index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.key
+    }
+}
+
+/// New 24 word English mnemonic.
+pub fn generate_mnemonic() -> String {
+    let mut entropy = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(entropy.as_mut_slice());
+    Mnemonic::from_entropy(entropy.as_slice())
+        .expect("32 bytes are valid entropy")
+        .to_string()
+}
+
+/// Seed of an English mnemonic, the checksum is checked.
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> Result<Zeroizing<[u8; 64]>, String> {
+    let words: Vec<&str> = mnemonic.split_whitespace().collect();
+    let mnemonic = Mnemonic::parse_in(bip39::Language::English, words.join(" "))
+        .map_err(|err| err.to_string())?;
+    Ok(Zeroizing::new(mnemonic.to_seed(passphrase)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BIP-39 English vectors with the passphrase `TREZOR`: mnemonic and seed.
+    const BIP39_VECTORS: &[(&str, &str)] = &[
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        ),
+        (
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+            "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+        ),
+        (
+            "scheme spot photo card baby mountain device kick cradle pact join borrow",
+            "ea725895aaae8d4c1cf682c1bfd2d358d52ed9f0f0591131b559e2724bb234fca05aa9c02c57407e04ee9dc3b454aa63fbff483a8b11de949624b9f1831a9612",
+        ),
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+        ),
+        (
+            "void come effort suffer camp survey warrior heavy shoot primary clutch crush open amazing screen patrol group space point ten exist slush involve unfold",
+            "01f5bced59dec48e362f2c45b5de68b9fd6c92c6634f44d6d40aab69056506f0e35524a518034ddc1192e1dacd32c1ed3eaa3c3b131c88ed8e7e54c49a5d0998",
+        ),
+    ];
+
+    /// BIP-32 test vectors 1 to 3: seed and the extended private key of each path.
+    const BIP32_VECTORS: &[(&str, &[(&str, &str)])] = &[
+        (
+            "000102030405060708090a0b0c0d0e0f",
+            &[
+                ("m", "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi"),
+                ("m/0'", "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7"),
+                ("m/0'/1", "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs"),
+                ("m/0'/1/2'", "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM"),
+                ("m/0'/1/2'/2", "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334"),
+                ("m/0'/1/2'/2/1000000000", "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76"),
+            ],
+        ),
+        (
+            "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+            &[
+                ("m", "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U"),
+                ("m/0", "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt"),
+                ("m/0/2147483647'", "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9"),
+                ("m/0/2147483647'/1", "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef"),
+                ("m/0/2147483647'/1/2147483646'", "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc"),
+                ("m/0/2147483647'/1/2147483646'/2", "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j"),
+            ],
+        ),
+        // the master key has a leading zero which has to be kept
+        (
+            "4b381541583be4423346c643850da4b320e46a87ae3d2a4e6da11eba819cd4acba45d239319ac14f863b8d5ab5a0d0c64d2e8a1e7d1457df2e5a3c51c73235be",
+            &[
+                ("m", "xprv9s21ZrQH143K25QhxbucbDDuQ4naNntJRi4KUfWT7xo4EKsHt2QJDu7KXp1A3u7Bi1j8ph3EGsZ9Xvz9dGuVrtHHs7pXeTzjuxBrCmmhgC6"),
+                ("m/0'", "xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L"),
+            ],
+        ),
+    ];
+
+    #[test]
+    fn bip39() {
+        for (mnemonic, seed) in BIP39_VECTORS {
+            assert_eq!(
+                hex::encode(mnemonic_to_seed(mnemonic, "TREZOR").unwrap()),
+                *seed
+            );
+        }
+        assert!(mnemonic_to_seed("zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo", "").is_err());
+    }
+
+    #[test]
+    fn bip32() {
+        for (seed, keys) in BIP32_VECTORS {
+            let master = ExtendedKey::master(&hex::decode(seed).unwrap()).unwrap();
+            for (path, xprv) in *keys {
+                let key = master
+                    .derive(&DerivationPath::parse(path).unwrap())
+                    .unwrap();
+                // version, depth, fingerprint and child number, then the chain code and the key after a zero byte
+                let xprv = bs58::decode(xprv).with_check(None).into_vec().unwrap();
+                assert_eq!(
+                    key.chain_code.as_slice(),
+                    &xprv[13..45],
+                    "chain code of {path}"
+                );
+                assert_eq!(
+                    key.signing_key().to_bytes().as_slice(),
+                    &xprv[46..78],
+                    "key of {path}"
+                );
+            }
+        }
+    }
+}
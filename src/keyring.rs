@@ -0,0 +1,434 @@
+use base64::prelude::*;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use super::db::{KeyId, KeyInfo, Storage, UserId};
+
+const KEK_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug)]
+pub enum KeyringError {
+    InvalidKek(String),
+    UnknownKek(String),
+    NotSealed,
+    SealFailed,
+    OpenFailed,
+}
+
+impl std::fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyringError::InvalidKek(err) => write!(f, "Invalid key-encryption key: {err}"),
+            KeyringError::UnknownKek(id) => write!(f, "Unknown key-encryption key: {id}"),
+            KeyringError::NotSealed => write!(f, "Key is stored unencrypted, run rewrap-keys"),
+            KeyringError::SealFailed => write!(f, "Key encryption failed"),
+            KeyringError::OpenFailed => write!(f, "Key decryption failed"),
+        }
+    }
+}
+
+/// Private key encrypted under a key-encryption key (KEK).
+///
+/// An empty `kek_id` marks a key stored in plaintext by an older version,
+/// such keys have to be sealed with `rewrap-keys` before they can be used.
+#[derive(Clone, Debug)]
+pub struct SealedKey {
+    pub kek_id: String,
+    /// Nonce followed by the AEAD ciphertext and tag.
+    pub ciphertext: Vec<u8>,
+}
+
+struct Kek {
+    id: String,
+    key: Zeroizing<[u8; KEK_LEN]>,
+}
+
+impl Kek {
+    fn new(key: [u8; KEK_LEN]) -> Self {
+        // identifier is derived from the key so it can't be mixed up in the configuration
+        let id = hex::encode(&Sha256::digest(key)[..8]);
+        Self {
+            id,
+            key: Zeroizing::new(key),
+        }
+    }
+
+    /// Parses a KEK given as 64 hex characters, base64 text or 32 raw bytes.
+    fn parse(value: &[u8]) -> Result<Self, KeyringError> {
+        if value.len() == KEK_LEN {
+            return Ok(Self::new(value.try_into().unwrap()));
+        }
+
+        let text = std::str::from_utf8(value)
+            .map_err(|_| KeyringError::InvalidKek("not a text or 32 byte binary key".into()))?
+            .trim();
+
+        let bytes = if text.len() == KEK_LEN * 2 && text.chars().all(|c| c.is_ascii_hexdigit()) {
+            hex::decode(text).map_err(|e| KeyringError::InvalidKek(e.to_string()))?
+        } else {
+            BASE64_STANDARD
+                .decode(text)
+                .map_err(|_| KeyringError::InvalidKek("expected hex or base64 encoding".into()))?
+        };
+
+        let key: [u8; KEK_LEN] = bytes
+            .try_into()
+            .map_err(|_| KeyringError::InvalidKek(format!("key must be {KEK_LEN} bytes long")))?;
+        Ok(Self::new(key))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(self.key.as_slice()))
+    }
+}
+
+/// Seals private keys under the current KEK and opens keys sealed under
+/// the current or any of the previous KEKs.
+pub struct Keyring {
+    current: Kek,
+    previous: Vec<Kek>,
+}
+
+impl Keyring {
+    /// Loads the current KEK from `WAAS_KEK` or `WAAS_KEK_FILE` and the KEK
+    /// being rotated out from `WAAS_KEK_PREVIOUS` or `WAAS_KEK_PREVIOUS_FILE`.
+    /// Returns `None` when no current KEK is configured.
+    pub fn from_env() -> Result<Option<Self>, KeyringError> {
+        let Some(current) = Self::kek_from_env("WAAS_KEK")? else {
+            return Ok(None);
+        };
+        let previous = Self::kek_from_env("WAAS_KEK_PREVIOUS")?
+            .into_iter()
+            .filter(|kek| kek.id != current.id)
+            .collect();
+
+        Ok(Some(Self { current, previous }))
+    }
+
+    /// Creates a keyring with a random KEK, keys sealed with it can't be
+    /// opened after restart.
+    pub fn ephemeral() -> Self {
+        let mut key = [0u8; KEK_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            current: Kek::new(key),
+            previous: Vec::new(),
+        }
+    }
+
+    fn kek_from_env(var: &str) -> Result<Option<Kek>, KeyringError> {
+        if let Some(value) = std::env::var_os(var).filter(|v| !v.is_empty()) {
+            let value = Zeroizing::new(value.into_encoded_bytes());
+            return Kek::parse(&value).map(Some);
+        }
+
+        let file_var = format!("{var}_FILE");
+        if let Some(path) = std::env::var_os(&file_var).filter(|v| !v.is_empty()) {
+            let value = Zeroizing::new(std::fs::read(&path).map_err(|e| {
+                KeyringError::InvalidKek(format!("can't read {}: {e}", path.to_string_lossy()))
+            })?);
+            return Kek::parse(&value).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    pub fn current_kek_id(&self) -> &str {
+        &self.current.id
+    }
+
+    /// Seals the key, the ciphertext only opens for the same user, key id and algorithm
+    /// so it can't be moved to another row of the storage.
+    pub fn seal(
+        &self,
+        user_id: UserId,
+        key_id: KeyId,
+        algorithm: &str,
+        key: &[u8],
+    ) -> Result<SealedKey, KeyringError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let aad = associated_data(user_id, key_id, algorithm);
+        let ciphertext = self
+            .current
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: key,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| KeyringError::SealFailed)?;
+
+        Ok(SealedKey {
+            kek_id: self.current.id.clone(),
+            ciphertext: [nonce.as_slice(), &ciphertext].concat(),
+        })
+    }
+
+    pub fn open(
+        &self,
+        user_id: UserId,
+        key: &KeyInfo,
+        sealed: &SealedKey,
+    ) -> Result<Zeroizing<Vec<u8>>, KeyringError> {
+        if sealed.kek_id.is_empty() {
+            return Err(KeyringError::NotSealed);
+        }
+        self.decrypt(sealed, &associated_data(user_id, key.id, &key.algorithm))
+    }
+
+    /// Opens a key sealed before the key id and algorithm were bound into the
+    /// associated data, such keys only bound their owner.
+    fn open_unbound(
+        &self,
+        user_id: UserId,
+        sealed: &SealedKey,
+    ) -> Result<Zeroizing<Vec<u8>>, KeyringError> {
+        self.decrypt(sealed, &user_id.to_be_bytes())
+    }
+
+    fn decrypt(&self, sealed: &SealedKey, aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeyringError> {
+        let kek = std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|kek| kek.id == sealed.kek_id)
+            .ok_or_else(|| KeyringError::UnknownKek(sealed.kek_id.clone()))?;
+
+        if sealed.ciphertext.len() < NONCE_LEN {
+            return Err(KeyringError::OpenFailed);
+        }
+        let (nonce, ciphertext) = sealed.ciphertext.split_at(NONCE_LEN);
+
+        kek.cipher()
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| KeyringError::OpenFailed)
+    }
+
+    /// Re-encrypts every stored key which is not sealed with the current KEK,
+    /// including legacy plaintext keys and keys sealed before the key id was
+    /// bound into the associated data. Returns number of rewrapped keys.
+    pub fn rewrap_all(&self, db: &mut dyn Storage) -> Result<usize, String> {
+        let mut count = 0;
+
+        for (user_id, key, sealed) in db.list_sealed_keys().map_err(|e| e.to_string())? {
+            let key_id = key.id;
+            let private_key = if sealed.kek_id.is_empty() {
+                Zeroizing::new(sealed.ciphertext.clone())
+            } else {
+                match self.open(user_id, &key, &sealed) {
+                    Ok(_) if sealed.kek_id == self.current.id => continue,
+                    Ok(private_key) => private_key,
+                    Err(KeyringError::OpenFailed) => self
+                        .open_unbound(user_id, &sealed)
+                        .map_err(|e| format!("key {key_id}: {e}"))?,
+                    Err(e) => return Err(format!("key {key_id}: {e}")),
+                }
+            };
+            let resealed = self
+                .seal(user_id, key_id, &key.algorithm, &private_key)
+                .map_err(|e| format!("key {key_id}: {e}"))?;
+            db.update_sealed_key(key_id, &resealed)
+                .map_err(|e| format!("key {key_id}: {e}"))?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+/// User id and key id in big endian followed by the algorithm name.
+fn associated_data(user_id: UserId, key_id: KeyId, algorithm: &str) -> Vec<u8> {
+    [
+        user_id.to_be_bytes().as_slice(),
+        &key_id.to_be_bytes(),
+        algorithm.as_bytes(),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemDb;
+
+    fn keyring(current: u8, previous: &[u8]) -> Keyring {
+        Keyring {
+            current: Kek::new([current; KEK_LEN]),
+            previous: previous
+                .iter()
+                .map(|kek| Kek::new([*kek; KEK_LEN]))
+                .collect(),
+        }
+    }
+
+    /// Seals the key the way it was sealed before the key id was bound.
+    fn seal_unbound(keyring: &Keyring, user_id: UserId, key: &[u8]) -> SealedKey {
+        let nonce = [7u8; NONCE_LEN];
+        let ciphertext = keyring
+            .current
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: key,
+                    aad: &user_id.to_be_bytes(),
+                },
+            )
+            .unwrap();
+        SealedKey {
+            kek_id: keyring.current.id.clone(),
+            ciphertext: [nonce.as_slice(), &ciphertext].concat(),
+        }
+    }
+
+    fn key_info(id: KeyId, algorithm: &str) -> KeyInfo {
+        KeyInfo {
+            id,
+            label: "main".to_string(),
+            algorithm: algorithm.to_string(),
+            created_at: 0,
+            exportable: true,
+            public_id: String::new(),
+        }
+    }
+
+    #[test]
+    fn parse_kek() {
+        let id = Kek::new([0xab; KEK_LEN]).id;
+        for value in [
+            "ab".repeat(KEK_LEN),
+            format!(" {}\n", "AB".repeat(KEK_LEN)),
+            BASE64_STANDARD.encode([0xab; KEK_LEN]),
+        ] {
+            assert_eq!(Kek::parse(value.as_bytes()).unwrap().id, id);
+        }
+        assert_eq!(Kek::parse(&[0xab; KEK_LEN]).unwrap().id, id);
+
+        assert!(Kek::parse("ab".repeat(20).as_bytes()).is_err());
+        assert!(Kek::parse(BASE64_STANDARD.encode([0xab; 16]).as_bytes()).is_err());
+        assert!(Kek::parse(b"not a key").is_err());
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let keyring = keyring(1, &[]);
+        let sealed = keyring.seal(1, 7, "secp256k1", b"private key").unwrap();
+        assert_eq!(sealed.kek_id, keyring.current_kek_id());
+        assert_eq!(
+            keyring
+                .open(1, &key_info(7, "secp256k1"), &sealed)
+                .unwrap()
+                .as_slice(),
+            b"private key"
+        );
+
+        // the ciphertext only opens for the row it was sealed for
+        for (user_id, key) in [
+            (2, key_info(7, "secp256k1")),
+            (1, key_info(8, "secp256k1")),
+            (1, key_info(7, "bip32-secp256k1")),
+        ] {
+            assert!(matches!(
+                keyring.open(user_id, &key, &sealed),
+                Err(KeyringError::OpenFailed)
+            ));
+        }
+
+        let mut tampered = sealed.clone();
+        *tampered.ciphertext.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            keyring.open(1, &key_info(7, "secp256k1"), &tampered),
+            Err(KeyringError::OpenFailed)
+        ));
+    }
+
+    #[test]
+    fn wrong_kek() {
+        let sealed = keyring(1, &[]).seal(1, 7, "secp256k1", b"key").unwrap();
+        let key = key_info(7, "secp256k1");
+
+        assert!(matches!(
+            keyring(2, &[]).open(1, &key, &sealed),
+            Err(KeyringError::UnknownKek(_))
+        ));
+        // a KEK with the id of another one doesn't open the key
+        let mut forged = keyring(2, &[]);
+        forged.current.id = sealed.kek_id.clone();
+        assert!(matches!(
+            forged.open(1, &key, &sealed),
+            Err(KeyringError::OpenFailed)
+        ));
+        // the previous KEK still opens keys during rotation
+        assert_eq!(
+            keyring(2, &[1]).open(1, &key, &sealed).unwrap().as_slice(),
+            b"key"
+        );
+    }
+
+    #[test]
+    fn rewrap() {
+        let mut db = MemDb::new();
+        assert_eq!(db.add_user("alice", "hash", None).unwrap(), 1);
+        let plaintext = db
+            .add_user_key(1, "legacy", "secp256k1", true, &|_| {
+                Ok(SealedKey {
+                    kek_id: String::new(),
+                    ciphertext: b"legacy key".to_vec(),
+                })
+            })
+            .unwrap();
+        let old = keyring(1, &[]);
+        let rotated = db
+            .add_user_key(1, "rotated", "secp256k1", true, &|key_id| {
+                old.seal(1, key_id, "secp256k1", b"rotated key")
+                    .map_err(|e| e.to_string())
+            })
+            .unwrap();
+        // sealed by the previous version under the KEK which is still current
+        let unbound = db
+            .add_user_key(1, "unbound", "secp256k1", true, &|_| {
+                Ok(seal_unbound(&keyring(2, &[]), 1, b"unbound key"))
+            })
+            .unwrap();
+
+        let keyring = keyring(2, &[1]);
+        let (key, sealed) = db.get_user_key(1, plaintext).unwrap();
+        assert!(matches!(
+            keyring.open(1, &key, &sealed),
+            Err(KeyringError::NotSealed)
+        ));
+        let (key, sealed) = db.get_user_key(1, unbound).unwrap();
+        assert!(matches!(
+            keyring.open(1, &key, &sealed),
+            Err(KeyringError::OpenFailed)
+        ));
+
+        assert_eq!(keyring.rewrap_all(&mut db).unwrap(), 3);
+        for (key_id, private_key) in [
+            (plaintext, b"legacy key".as_slice()),
+            (rotated, b"rotated key".as_slice()),
+            (unbound, b"unbound key".as_slice()),
+        ] {
+            let (key, sealed) = db.get_user_key(1, key_id).unwrap();
+            assert_eq!(sealed.kek_id, keyring.current_kek_id());
+            assert_eq!(
+                keyring.open(1, &key, &sealed).unwrap().as_slice(),
+                private_key
+            );
+        }
+        assert_eq!(keyring.rewrap_all(&mut db).unwrap(), 0);
+    }
+}
@@ -0,0 +1,436 @@
+//! Encrypted key files in the Web3 Secret Storage format (Ethereum keystore v3).
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use poem_openapi::Enum;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+/// scrypt parameters of exported files, 64 MiB of memory.
+const SCRYPT_LOG_N: u8 = 16;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const PBKDF2_ROUNDS: u32 = 262_144;
+/// Limits of imported files, the KDF parameters are chosen by whoever wrote
+/// the file. Geth writes p = 1 or 6 and a few seconds of work at most are
+/// allowed per import.
+const MAX_SCRYPT_MEMORY: u64 = 256 * 1024 * 1024;
+const MAX_SCRYPT_P: u32 = 6;
+const MAX_PBKDF2_ROUNDS: u32 = 1_000_000;
+
+/// Who may export keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KeyExportPolicy {
+    /// Keys can be exported unless they are marked non-exportable.
+    #[default]
+    Allowed,
+    /// No key can be exported.
+    Disabled,
+}
+
+impl std::str::FromStr for KeyExportPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allowed" => Ok(KeyExportPolicy::Allowed),
+            "disabled" => Ok(KeyExportPolicy::Disabled),
+            _ => Err(format!("Unknown key export policy '{s}'")),
+        }
+    }
+}
+
+/// Key derivation of the encryption key from the password.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Enum)]
+pub enum Kdf {
+    /// scrypt with N = 2^16, r = 8, p = 1.
+    #[default]
+    #[serde(rename = "scrypt")]
+    #[oai(rename = "scrypt")]
+    Scrypt,
+    /// PBKDF2-HMAC-SHA256 with 262144 iterations.
+    #[serde(rename = "pbkdf2")]
+    #[oai(rename = "pbkdf2")]
+    Pbkdf2,
+}
+
+impl std::str::FromStr for Kdf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scrypt" => Ok(Kdf::Scrypt),
+            "pbkdf2" => Ok(Kdf::Pbkdf2),
+            _ => Err(format!("Unknown KDF '{s}'")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Invalid(String),
+    WrongPassword,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    id: String,
+    /// Ethereum address without `0x`, optional in files of other wallets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    crypto: Crypto,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Crypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    kdf: Kdf,
+    kdfparams: KdfParams,
+    mac: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum KdfParams {
+    Scrypt {
+        dklen: usize,
+        n: u64,
+        r: u32,
+        p: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        dklen: usize,
+        c: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+impl KdfParams {
+    fn derive(&self, password: &str) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+        let invalid = |err: &str| KeystoreError::Invalid(err.to_string());
+        let mut key = Zeroizing::new([0u8; 32]);
+        match self {
+            KdfParams::Scrypt {
+                dklen,
+                n,
+                r,
+                p,
+                salt,
+            } => {
+                if *dklen != 32 || !n.is_power_of_two() || *n < 2 || *r == 0 || *p == 0 {
+                    return Err(invalid("unsupported scrypt parameters"));
+                }
+                // RFC 7914 requires N < 2^(16 r), which the scrypt test vector of the format breaks
+                if n.trailing_zeros() >= 16 * r {
+                    return Err(invalid("scrypt n must be below 2^(16 r)"));
+                }
+                if 128 * *r as u64 * n > MAX_SCRYPT_MEMORY || *p > MAX_SCRYPT_P {
+                    return Err(invalid("scrypt parameters exceed the limits"));
+                }
+                let salt = hex::decode(salt).map_err(|_| invalid("salt is not hex"))?;
+                let params = scrypt::Params::new(n.trailing_zeros() as u8, *r, *p, 32)
+                    .map_err(|_| invalid("unsupported scrypt parameters"))?;
+                scrypt::scrypt(password.as_bytes(), &salt, &params, key.as_mut_slice())
+                    .map_err(|_| invalid("unsupported scrypt parameters"))?;
+            }
+            KdfParams::Pbkdf2 {
+                dklen,
+                c,
+                prf,
+                salt,
+            } => {
+                if *dklen != 32 || prf != "hmac-sha256" || *c == 0 {
+                    return Err(invalid("unsupported pbkdf2 parameters"));
+                }
+                if *c > MAX_PBKDF2_ROUNDS {
+                    return Err(invalid("pbkdf2 parameters exceed the limits"));
+                }
+                let salt = hex::decode(salt).map_err(|_| invalid("salt is not hex"))?;
+                pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, *c, key.as_mut_slice());
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// `keccak256(derived_key[16..32] || ciphertext)`
+fn mac(derived_key: &[u8; 32], ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(&derived_key[16..]);
+    hasher.update(ciphertext);
+    hasher.finalize().into()
+}
+
+/// Random version 4 UUID.
+fn uuid_v4() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Encrypts the private key with AES-128-CTR under a key derived from the
+/// password, `address` is the Ethereum address of the key without `0x`.
+pub fn encrypt(key: &[u8], address: &str, password: &str, kdf: Kdf) -> String {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+
+    let kdfparams = match kdf {
+        Kdf::Scrypt => KdfParams::Scrypt {
+            dklen: 32,
+            n: 1 << SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+        },
+        Kdf::Pbkdf2 => KdfParams::Pbkdf2 {
+            dklen: 32,
+            c: PBKDF2_ROUNDS,
+            prf: "hmac-sha256".to_string(),
+            salt: hex::encode(salt),
+        },
+    };
+    let derived_key = kdfparams
+        .derive(password)
+        .expect("parameters of exported files are supported");
+
+    let mut ciphertext = key.to_vec();
+    Aes128Ctr::new(derived_key[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+    let keystore = Keystore {
+        version: 3,
+        id: uuid_v4(),
+        address: Some(address.to_string()),
+        crypto: Crypto {
+            cipher: "aes-128-ctr".to_string(),
+            cipherparams: CipherParams {
+                iv: hex::encode(iv),
+            },
+            mac: hex::encode(mac(&derived_key, &ciphertext)),
+            ciphertext: hex::encode(ciphertext),
+            kdf,
+            kdfparams,
+        },
+    };
+    serde_json::to_string_pretty(&keystore).expect("keystore serializes to JSON")
+}
+
+/// Decrypts the private key of a keystore v3 file, the MAC is checked
+/// before decryption. Returns the key and the address given in the file.
+pub fn decrypt(
+    json: &str,
+    password: &str,
+) -> Result<(Zeroizing<Vec<u8>>, Option<String>), KeystoreError> {
+    let keystore: Keystore =
+        serde_json::from_str(json).map_err(|err| KeystoreError::Invalid(err.to_string()))?;
+    let crypto = &keystore.crypto;
+    if keystore.version != 3 {
+        return Err(KeystoreError::Invalid(format!(
+            "version {} is not supported",
+            keystore.version
+        )));
+    }
+    if crypto.cipher != "aes-128-ctr" {
+        return Err(KeystoreError::Invalid(format!(
+            "cipher {} is not supported",
+            crypto.cipher
+        )));
+    }
+    match (&crypto.kdf, &crypto.kdfparams) {
+        (Kdf::Scrypt, KdfParams::Scrypt { .. }) | (Kdf::Pbkdf2, KdfParams::Pbkdf2 { .. }) => {}
+        _ => {
+            return Err(KeystoreError::Invalid(
+                "kdfparams don't match the kdf".to_string(),
+            ))
+        }
+    }
+
+    let hex_field = |name: &str, value: &str| {
+        hex::decode(value).map_err(|_| KeystoreError::Invalid(format!("{name} is not hex")))
+    };
+    let ciphertext = hex_field("ciphertext", &crypto.ciphertext)?;
+    let iv: [u8; 16] = hex_field("iv", &crypto.cipherparams.iv)?
+        .try_into()
+        .map_err(|_| KeystoreError::Invalid("iv must be 16 bytes".to_string()))?;
+    let expected_mac = hex_field("mac", &crypto.mac)?;
+
+    let derived_key = crypto.kdfparams.derive(password)?;
+    // compared in constant time so the MAC can't be guessed byte by byte
+    if !bool::from(mac(&derived_key, &ciphertext).ct_eq(expected_mac.as_slice())) {
+        return Err(KeystoreError::WrongPassword);
+    }
+
+    let mut key = Zeroizing::new(ciphertext);
+    Aes128Ctr::new(derived_key[..16].into(), &iv.into()).apply_keystream(&mut key);
+    Ok((key, keystore.address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::ethereum_address;
+    use k256::ecdsa::SigningKey;
+
+    /// Test vectors of the Web3 Secret Storage Definition, password
+    /// "testpassword". The scrypt one uses r = 1 with N = 2^18, beyond the
+    /// RFC 7914 bound.
+    const PBKDF2_VECTOR: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+    const SCRYPT_VECTOR: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 8,
+                "r": 1,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+    /// The key of the vectors under scrypt with N = 2^12, r = 8 and p = 1,
+    /// written with the scrypt of OpenSSL.
+    const SCRYPT_FILE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "3b4309355ad643f2b15cfb6a83a7f6f328e7a6459a56ab8c6e25a89c8f43eb80",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 4096,
+                "p": 1,
+                "r": 8,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "994d83f6bfb7e6e3aa95980f72b6ad87db9d352789d0f2e433cf777425db3a42"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+    const VECTOR_KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+    const VECTOR_ADDRESS: &str = "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b";
+
+    fn address(key: &[u8]) -> String {
+        ethereum_address(SigningKey::from_slice(key).unwrap().verifying_key()).to_lowercase()
+    }
+
+    #[test]
+    fn decrypt_test_vectors() {
+        for json in [PBKDF2_VECTOR, SCRYPT_FILE] {
+            let (key, file_address) = decrypt(json, "testpassword").unwrap();
+            assert_eq!(hex::encode(key.as_slice()), VECTOR_KEY);
+            assert_eq!(file_address, None);
+            assert_eq!(address(&key), VECTOR_ADDRESS);
+        }
+
+        match decrypt(SCRYPT_VECTOR, "testpassword") {
+            Err(KeystoreError::Invalid(err)) => assert_eq!(err, "scrypt n must be below 2^(16 r)"),
+            _ => panic!("scrypt beyond the RFC 7914 bound is refused"),
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let key = hex::decode(VECTOR_KEY).unwrap();
+        let address = &VECTOR_ADDRESS[2..];
+        for kdf in [Kdf::Scrypt, Kdf::Pbkdf2] {
+            let json = encrypt(&key, address, "correct horse", kdf);
+            let keystore: Keystore = serde_json::from_str(&json).unwrap();
+            assert_eq!(keystore.crypto.kdf, kdf);
+
+            let (decrypted, file_address) = decrypt(&json, "correct horse").unwrap();
+            assert_eq!(decrypted.as_slice(), key.as_slice());
+            assert_eq!(file_address.as_deref(), Some(address));
+        }
+    }
+
+    #[test]
+    fn wrong_password() {
+        assert!(matches!(
+            decrypt(PBKDF2_VECTOR, "wrongpassword"),
+            Err(KeystoreError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn kdf_limits() {
+        let with_params = |vector: &str, params: &str| {
+            let mut json: serde_json::Value = serde_json::from_str(vector).unwrap();
+            let kdfparams = json["crypto"]["kdfparams"].as_object_mut().unwrap();
+            for (name, value) in serde_json::from_str::<serde_json::Value>(params)
+                .unwrap()
+                .as_object()
+                .unwrap()
+            {
+                kdfparams.insert(name.clone(), value.clone());
+            }
+            json.to_string()
+        };
+
+        for json in [
+            // 512 MiB of memory
+            with_params(SCRYPT_FILE, r#"{ "n": 524288, "r": 8, "p": 1 }"#),
+            with_params(SCRYPT_FILE, r#"{ "n": 1024, "r": 8, "p": 7 }"#),
+            with_params(SCRYPT_FILE, r#"{ "n": 1000, "r": 8, "p": 1 }"#),
+            with_params(SCRYPT_FILE, r#"{ "n": 65536, "r": 1, "p": 1 }"#),
+            with_params(PBKDF2_VECTOR, r#"{ "c": 1000001 }"#),
+            with_params(PBKDF2_VECTOR, r#"{ "prf": "hmac-sha512" }"#),
+        ] {
+            assert!(
+                matches!(
+                    decrypt(&json, "testpassword"),
+                    Err(KeystoreError::Invalid(_))
+                ),
+                "{json}"
+            );
+        }
+    }
+}
@@ -2,19 +2,37 @@ use poem::{
     listener::TcpListener,
     middleware::{CatchPanic, Tracing},
     session::{CookieConfig, CookieSession},
-    web::cookie::CookieKey,
+    web::cookie::{CookieKey, SameSite},
     EndpointExt, Server,
 };
 use service::SignService;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use zeroize::Zeroizing;
 
-use db::MemDb;
+use config::Config;
+use db::{MemDb, SharedStorage, SqliteDb};
+use keyring::Keyring;
+use password::PasswordHashing;
+use registration::RegistrationMode;
 use web_app::WebApp;
 
+mod api;
+mod bitcoin;
+mod config;
 mod db;
+mod eip712;
+mod eth_tx;
+mod hd;
+mod keyring;
+mod keystore;
+mod nostr;
+mod password;
+mod psbt;
+mod registration;
 mod service;
 mod template;
+mod token;
 mod web_app;
 
 #[tokio::main]
@@ -24,16 +42,109 @@ async fn main() -> Result<(), std::io::Error> {
     }
     tracing_subscriber::fmt::init();
 
-    let db = MemDb::new();
+    let config = Config::from_env().map_err(std::io::Error::other)?;
+    let passwords = PasswordHashing::new(config.password.clone()).map_err(std::io::Error::other)?;
+
+    let db: SharedStorage =
+        match &config.sqlite_path {
+            Some(path) => SharedStorage::new(SqliteDb::open(path).map_err(|e| {
+                std::io::Error::other(format!("Failed to open database {path}: {e}"))
+            })?),
+            None => SharedStorage::new(MemDb::new()),
+        };
+
+    let keyring = match Keyring::from_env().map_err(|e| std::io::Error::other(e.to_string()))? {
+        Some(keyring) => keyring,
+        None if config.sqlite_path.is_none() => {
+            tracing::warn!("No key-encryption key configured, using an ephemeral one");
+            Keyring::ephemeral()
+        }
+        None => {
+            return Err(std::io::Error::other(
+                "Key-encryption key is required, set WAAS_KEK or WAAS_KEK_FILE",
+            ))
+        }
+    };
+
+    match std::env::args().nth(1).as_deref() {
+        Some("rewrap-keys") => {
+            if config.sqlite_path.is_none() {
+                return Err(std::io::Error::other(
+                    "Keys can only be rewrapped in persistent storage, set WAAS_SQLITE_PATH",
+                ));
+            }
+
+            let kek_id = keyring.current_kek_id().to_string();
+            let count = db
+                .run(move |db| keyring.rewrap_all(db))
+                .await
+                .map_err(std::io::Error::other)?;
+            println!("Rewrapped {count} key(s) under key-encryption key {kek_id}");
+            return Ok(());
+        }
+        Some("add-user") => {
+            let username = std::env::args()
+                .nth(2)
+                .ok_or_else(|| std::io::Error::other("Usage: waas add-user <username>"))?;
+            if config.sqlite_path.is_none() {
+                return Err(std::io::Error::other(
+                    "Users can only be added to persistent storage, set WAAS_SQLITE_PATH",
+                ));
+            }
+
+            // password is read from stdin so it doesn't end up in the shell history
+            let mut password = Zeroizing::new(String::new());
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+
+            let user_id =
+                registration::create_user(&username, password, None, &db, &Arc::new(passwords))
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("Added user {username} with id {user_id}");
+            return Ok(());
+        }
+        Some("create-invite") => {
+            if config.sqlite_path.is_none() {
+                return Err(std::io::Error::other(
+                    "Invites can only be stored in persistent storage, set WAAS_SQLITE_PATH",
+                ));
+            }
+
+            let code = registration::generate_invite_code();
+            db.add_invite(&token::hash_token(&code))
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("{code}");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // accounts are only created by `add-user` or with invite codes of `create-invite`, which
+    // can't reach the memory of this process
+    if config.sqlite_path.is_none() && config.registration != RegistrationMode::Open {
+        return Err(std::io::Error::other(
+            "In-memory storage needs WAAS_REGISTRATION=open, accounts of the other modes are only created in persistent storage, set WAAS_SQLITE_PATH",
+        ));
+    }
+
     let sign_service = SignService::default();
     let app = WebApp::new();
 
     let router = WebApp::setup_route()
         .data(Arc::new(Mutex::new(app)))
-        .data(Arc::new(Mutex::new(db)))
+        .data(db)
+        .data(Arc::new(keyring))
+        .data(Arc::new(passwords))
+        .data(config.registration)
+        .data(config.key_export)
         .data(Arc::new(Mutex::new(sign_service)))
         .with(CookieSession::new(
-            CookieConfig::private(CookieKey::generate()).secure(false),
+            // strict so that other sites can't make requests with the session
+            CookieConfig::private(CookieKey::generate())
+                .secure(false)
+                .same_site(SameSite::Strict),
         ))
         .with(Tracing)
         .with(CatchPanic::new())
@@ -0,0 +1,126 @@
+//! Nostr events (NIP-01) and `npub` public keys (NIP-19).
+
+use bech32::{primitives::decode::CheckedHrpstring, Bech32, Hrp};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+const NPUB: Hrp = Hrp::parse_unchecked("npub");
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventJson {
+    id: Option<String>,
+    pubkey: Option<String>,
+    created_at: u64,
+    kind: u16,
+    #[serde(default)]
+    tags: Vec<Vec<String>>,
+    content: String,
+    sig: Option<String>,
+}
+
+/// Event as given for signing, `id` and `sig` of an already signed event
+/// are checked or kept for verification.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// x-only key of the author, filled in by signing if missing.
+    pub pubkey: Option<[u8; 32]>,
+    pub created_at: u64,
+    pub kind: u16,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: Option<[u8; 64]>,
+}
+
+/// NIP-01 requires lowercase hex, relays reject events with uppercase hex.
+fn decode_hex<const N: usize>(name: &str, input: &str) -> Result<[u8; N], String> {
+    Some(input)
+        .filter(|input| !input.bytes().any(|c| c.is_ascii_uppercase()))
+        .and_then(|input| hex::decode(input).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(format!("{name} must be {N} bytes in lowercase hex"))
+}
+
+impl Event {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let json: EventJson = serde_json::from_str(input).map_err(|err| err.to_string())?;
+        let event = Event {
+            pubkey: json
+                .pubkey
+                .map(|pubkey| decode_hex("pubkey", &pubkey))
+                .transpose()?,
+            created_at: json.created_at,
+            kind: json.kind,
+            tags: json.tags,
+            content: json.content,
+            sig: json.sig.map(|sig| decode_hex("sig", &sig)).transpose()?,
+        };
+
+        if let Some(id) = json.id {
+            let id: [u8; 32] = decode_hex("id", &id)?;
+            let pubkey = event.pubkey.ok_or("id given without pubkey")?;
+            if id != event.id(&pubkey) {
+                return Err("id doesn't match the event".to_string());
+            }
+        }
+        Ok(event)
+    }
+
+    /// SHA-256 of the canonical serialization
+    /// `[0, pubkey, created_at, kind, tags, content]`.
+    pub fn id(&self, pubkey: &[u8; 32]) -> [u8; 32] {
+        let serialized = json!([
+            0,
+            hex::encode(pubkey),
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        Sha256::digest(serialized.to_string().as_bytes()).into()
+    }
+
+    /// Complete event with `id`, `pubkey` and `sig`.
+    pub fn to_signed_json(&self, pubkey: &[u8; 32], sig: &[u8; 64]) -> String {
+        json!({
+            "id": hex::encode(self.id(pubkey)),
+            "pubkey": hex::encode(pubkey),
+            "created_at": self.created_at,
+            "kind": self.kind,
+            "tags": self.tags,
+            "content": self.content,
+            "sig": hex::encode(sig),
+        })
+        .to_string()
+    }
+}
+
+pub fn npub(x_only: &[u8; 32]) -> String {
+    bech32::encode::<Bech32>(NPUB, x_only).expect("32 bytes fit into bech32")
+}
+
+/// x-only key of a bech32 `npub1...` key.
+pub fn parse_npub(input: &str) -> Option<[u8; 32]> {
+    let decoded = CheckedHrpstring::new::<Bech32>(input.trim()).ok()?;
+    if decoded.hrp() != NPUB {
+        return None;
+    }
+    decoded.byte_iter().collect::<Vec<_>>().try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercase_hex() {
+        let event = r#"{"pubkey": "PUBKEY", "created_at": 1, "kind": 1, "content": ""}"#;
+        let pubkey = "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659";
+        assert!(Event::parse(&event.replace("PUBKEY", pubkey)).is_ok());
+        assert_eq!(
+            Event::parse(&event.replace("PUBKEY", &pubkey.to_uppercase())).unwrap_err(),
+            "pubkey must be 32 bytes in lowercase hex"
+        );
+    }
+}
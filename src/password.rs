@@ -0,0 +1,263 @@
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pwhash::bcrypt::{self, BcryptSetup, BcryptVariant};
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+
+/// Checks a new password. Only the length is enforced, composition rules
+/// are left out as recommended by NIST SP 800-63B.
+pub fn check_password_policy(username: &str, password: &str) -> Result<(), String> {
+    let len = password.chars().count();
+    if len < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "Password must have at least {MIN_PASSWORD_LEN} characters"
+        ));
+    }
+    if len > MAX_PASSWORD_LEN {
+        return Err(format!(
+            "Password must have at most {MAX_PASSWORD_LEN} characters"
+        ));
+    }
+    if password.eq_ignore_ascii_case(username) {
+        return Err("Password must differ from the username".to_string());
+    }
+    Ok(())
+}
+
+/// Algorithm used to hash new passwords.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PasswordAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+impl std::str::FromStr for PasswordAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argon2id" => Ok(PasswordAlgorithm::Argon2id),
+            "bcrypt" => Ok(PasswordAlgorithm::Bcrypt),
+            _ => Err(format!("Unknown password hash algorithm '{s}'")),
+        }
+    }
+}
+
+/// Parameters for hashing new passwords. Stored hashes carry their own
+/// parameters, so changing these only affects new and rehashed passwords.
+#[derive(Clone, Debug)]
+pub struct PasswordConfig {
+    pub algorithm: PasswordAlgorithm,
+    pub bcrypt_cost: u32,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    /// Argon2id with the parameters recommended by OWASP.
+    fn default() -> Self {
+        Self {
+            algorithm: PasswordAlgorithm::Argon2id,
+            bcrypt_cost: 12,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+        }
+    }
+}
+
+/// Hashes passwords with a random salt per password and checks passwords
+/// against stored bcrypt or Argon2 (PHC string format) hashes.
+pub struct PasswordHashing {
+    config: PasswordConfig,
+    argon2: Argon2<'static>,
+    /// Hash of a random password made with the configured parameters,
+    /// unknown users are checked against it.
+    dummy_hash: String,
+}
+
+impl PasswordHashing {
+    pub fn new(config: PasswordConfig) -> Result<Self, String> {
+        if !(4..=31).contains(&config.bcrypt_cost) {
+            return Err(format!(
+                "bcrypt cost must be between 4 and 31, got {}",
+                config.bcrypt_cost
+            ));
+        }
+        let params = Params::new(
+            config.argon2_memory_kib,
+            config.argon2_iterations,
+            config.argon2_parallelism,
+            None,
+        )
+        .map_err(|e| format!("Invalid Argon2 parameters: {e}"))?;
+
+        let mut hashing = Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+            config,
+            dummy_hash: String::new(),
+        };
+        hashing.dummy_hash = hashing.hash(SaltString::generate(&mut OsRng).as_str())?;
+        Ok(hashing)
+    }
+
+    /// Hash to verify passwords of unknown users against, so the time taken
+    /// doesn't reveal whether the user exists.
+    pub fn dummy_hash(&self) -> &str {
+        &self.dummy_hash
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, String> {
+        match self.config.algorithm {
+            PasswordAlgorithm::Argon2id => self
+                .argon2
+                .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+                .map(|hash| hash.to_string())
+                .map_err(|e| e.to_string()),
+            PasswordAlgorithm::Bcrypt => bcrypt::hash_with(
+                BcryptSetup {
+                    salt: None,
+                    cost: Some(self.config.bcrypt_cost),
+                    variant: Some(BcryptVariant::V2b),
+                },
+                password,
+            )
+            .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> bool {
+        if is_bcrypt(hash) {
+            return bcrypt::verify(password, hash);
+        }
+
+        // parameters are taken from the stored hash, not from the configuration
+        PasswordHash::new(hash)
+            .map(|hash| {
+                self.argon2
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    }
+
+    /// Whether the hash was made with another algorithm or parameters than
+    /// the configured ones, such hashes are replaced on the next login.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        match self.config.algorithm {
+            PasswordAlgorithm::Bcrypt => {
+                !is_bcrypt(hash) || bcrypt_cost(hash) != Some(self.config.bcrypt_cost)
+            }
+            PasswordAlgorithm::Argon2id => {
+                let Ok(hash) = PasswordHash::new(hash) else {
+                    return true;
+                };
+                let Ok(params) = Params::try_from(&hash) else {
+                    return true;
+                };
+                let current = self.argon2.params();
+
+                hash.algorithm != Algorithm::Argon2id.ident()
+                    || hash.version != Some(Version::V0x13.into())
+                    || params.m_cost() != current.m_cost()
+                    || params.t_cost() != current.t_cost()
+                    || params.p_cost() != current.p_cost()
+            }
+        }
+    }
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+/// Cost of a `$2b$<cost>$<salt and hash>` bcrypt hash.
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    hash.split('$').nth(2)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// bcrypt hash of "legacy password" in the format of the former scheme.
+    const LEGACY_HASH: &str = "$2y$05$VXFyz72zGnJr4TfbMfzIVu3/31loJ79QALBCXHx3eE3Ogxw0bB2bu";
+
+    fn with_config(config: PasswordConfig) -> PasswordHashing {
+        PasswordHashing::new(config).unwrap()
+    }
+
+    #[test]
+    fn argon2id() {
+        let hashing = with_config(PasswordConfig::default());
+        let hash = hashing.hash("correct horse").unwrap();
+        assert!(
+            hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"),
+            "{hash}"
+        );
+        assert!(hashing.verify("correct horse", &hash));
+        assert!(!hashing.verify("correct horsE", &hash));
+        assert!(!hashing.needs_rehash(&hash));
+
+        // every hash has its own salt
+        assert_ne!(hashing.hash("correct horse").unwrap(), hash);
+        assert!(!hashing.verify("correct horse", "not a hash"));
+    }
+
+    #[test]
+    fn legacy_bcrypt() {
+        let hashing = with_config(PasswordConfig::default());
+        assert!(hashing.verify("legacy password", LEGACY_HASH));
+        assert!(!hashing.verify("wrong password", LEGACY_HASH));
+        assert!(hashing.needs_rehash(LEGACY_HASH));
+
+        let bcrypt = with_config(PasswordConfig {
+            algorithm: PasswordAlgorithm::Bcrypt,
+            bcrypt_cost: 5,
+            ..PasswordConfig::default()
+        });
+        let hash = bcrypt.hash("correct horse").unwrap();
+        assert!(hash.starts_with("$2b$05$"), "{hash}");
+        assert!(bcrypt.verify("correct horse", &hash));
+        assert!(!bcrypt.needs_rehash(&hash));
+        assert!(!bcrypt.needs_rehash(LEGACY_HASH));
+        assert!(bcrypt.needs_rehash(&hashing.hash("correct horse").unwrap()));
+    }
+
+    #[test]
+    fn rehash_weaker_argon2() {
+        let hashing = with_config(PasswordConfig::default());
+        for weaker in [
+            PasswordConfig {
+                argon2_memory_kib: 8 * 1024,
+                ..PasswordConfig::default()
+            },
+            PasswordConfig {
+                argon2_iterations: 1,
+                ..PasswordConfig::default()
+            },
+        ] {
+            let hash = with_config(weaker).hash("correct horse").unwrap();
+            // still verified with the parameters of the hash
+            assert!(hashing.verify("correct horse", &hash));
+            assert!(hashing.needs_rehash(&hash));
+        }
+    }
+
+    #[test]
+    fn password_policy() {
+        assert!(check_password_policy("alice", "1234567").is_err());
+        assert!(check_password_policy("alice", "12345678").is_ok());
+        // characters are counted, not bytes
+        assert!(check_password_policy("alice", "ééééééé").is_err());
+        assert!(check_password_policy("alice", &"é".repeat(128)).is_ok());
+        assert!(check_password_policy("alice", &"a".repeat(129)).is_err());
+        assert!(check_password_policy("alice123", "ALICE123").is_err());
+    }
+}
@@ -0,0 +1,846 @@
+//! Partially signed Bitcoin transactions (BIP-174), version 0.
+//!
+//! Inputs paying to P2WPKH or P2PKH outputs of a key are signed with the
+//! BIP-143 or the legacy sighash, the signatures are added as partial
+//! signatures. Fields which are not needed are kept as they are.
+
+use base64::prelude::*;
+use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey, VerifyingKey};
+
+use super::bitcoin::{
+    is_p2pkh_script, p2pkh_script, p2wpkh_script, script_address, sha256d, write_compact_size,
+};
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const GLOBAL_VERSION: u8 = 0xfb;
+const IN_NON_WITNESS_UTXO: u8 = 0x00;
+const IN_WITNESS_UTXO: u8 = 0x01;
+const IN_PARTIAL_SIG: u8 = 0x02;
+const IN_SIGHASH_TYPE: u8 = 0x03;
+const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+const SIGHASH_ALL: u32 = 0x01;
+const SIGHASH_NONE: u32 = 0x02;
+const SIGHASH_SINGLE: u32 = 0x03;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+const SATS_PER_BTC: u64 = 100_000_000;
+
+/// Key-value pairs of a map, the key includes its type byte.
+type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxIn {
+    pub prev_txid: [u8; 32],
+    pub prev_vout: u32,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxOut {
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tx {
+    pub version: u32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+/// Reads consensus encoded data.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.data.len() {
+            return Err("unexpected end of data".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn compact_size(&mut self) -> Result<u64, String> {
+        Ok(match self.u8()? {
+            0xfd => u16::from_le_bytes(self.array()?) as u64,
+            0xfe => self.u32()? as u64,
+            0xff => self.u64()?,
+            n => n as u64,
+        })
+    }
+
+    fn var_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.compact_size()?;
+        self.bytes(usize::try_from(len).map_err(|_| "length too large")?)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_compact_size(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+impl TxOut {
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        Ok(TxOut {
+            value: reader.u64()?,
+            script_pubkey: reader.var_bytes()?.to_vec(),
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.value.to_le_bytes());
+        write_var_bytes(out, &self.script_pubkey);
+    }
+}
+
+impl Tx {
+    /// Parses a transaction in either the legacy or the segwit serialization,
+    /// witnesses are skipped.
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data };
+        let version = reader.u32()?;
+        let mut input_count = reader.compact_size()?;
+        let segwit = input_count == 0;
+        if segwit {
+            if reader.u8()? != 0x01 {
+                return Err("invalid segwit flag".to_string());
+            }
+            input_count = reader.compact_size()?;
+        }
+
+        let inputs = (0..input_count)
+            .map(|_| {
+                Ok(TxIn {
+                    prev_txid: reader.array()?,
+                    prev_vout: reader.u32()?,
+                    script_sig: reader.var_bytes()?.to_vec(),
+                    sequence: reader.u32()?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let output_count = reader.compact_size()?;
+        let outputs = (0..output_count)
+            .map(|_| TxOut::read(&mut reader))
+            .collect::<Result<Vec<_>, String>>()?;
+        if segwit {
+            for _ in &inputs {
+                for _ in 0..reader.compact_size()? {
+                    reader.var_bytes()?;
+                }
+            }
+        }
+        let lock_time = reader.u32()?;
+        if !reader.is_empty() {
+            return Err("trailing data after transaction".to_string());
+        }
+
+        Ok(Tx {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+    }
+
+    /// Serialization without witnesses.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = self.version.to_le_bytes().to_vec();
+        write_compact_size(&mut out, self.inputs.len() as u64);
+        for input in &self.inputs {
+            out.extend_from_slice(&input.prev_txid);
+            out.extend_from_slice(&input.prev_vout.to_le_bytes());
+            write_var_bytes(&mut out, &input.script_sig);
+            out.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        write_compact_size(&mut out, self.outputs.len() as u64);
+        for output in &self.outputs {
+            output.write(&mut out);
+        }
+        out.extend_from_slice(&self.lock_time.to_le_bytes());
+        out
+    }
+
+    /// Transaction id in internal byte order.
+    pub fn txid(&self) -> [u8; 32] {
+        sha256d(&self.serialize())
+    }
+}
+
+/// Formats an amount in BTC with all eight decimals.
+pub fn format_btc(sats: u64) -> String {
+    format!("{}.{:08} BTC", sats / SATS_PER_BTC, sats % SATS_PER_BTC)
+}
+
+/// Formats a txid as usually displayed, in reversed byte order.
+fn format_txid(txid: &[u8; 32]) -> String {
+    let mut txid = *txid;
+    txid.reverse();
+    hex::encode(txid)
+}
+
+/// Address of the output script or the script in hex if it is not standard.
+fn format_script(script: &[u8]) -> String {
+    script_address(script).unwrap_or_else(|| format!("script {}", hex::encode(script)))
+}
+
+/// Input as shown for review.
+pub struct InputView {
+    pub outpoint: String,
+    pub address: Option<String>,
+    pub value: Option<u64>,
+    /// Whether the value comes from the previous transaction. A witness UTXO
+    /// alone can lie about it, see [`Psbt::sign`].
+    pub value_verified: bool,
+    /// Whether the input is signed by the key.
+    pub signable: bool,
+}
+
+/// Output as shown for review.
+pub struct OutputView {
+    pub address: String,
+    pub value: u64,
+}
+
+/// Partial signature of an input.
+pub struct PartialSignature {
+    pub input: usize,
+    pub public_key: Vec<u8>,
+    /// DER signature followed by the sighash type byte.
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Psbt {
+    pub tx: Tx,
+    global: Map,
+    inputs: Vec<Map>,
+    outputs: Vec<Map>,
+}
+
+fn read_map(reader: &mut Reader) -> Result<Map, String> {
+    let mut map: Map = Vec::new();
+    loop {
+        let key = reader.var_bytes()?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        if map.iter().any(|(existing, _)| existing == key) {
+            return Err(format!("duplicate key {}", hex::encode(key)));
+        }
+        let value = reader.var_bytes()?;
+        map.push((key.to_vec(), value.to_vec()));
+    }
+}
+
+fn write_map(out: &mut Vec<u8>, map: &Map) {
+    for (key, value) in map {
+        write_var_bytes(out, key);
+        write_var_bytes(out, value);
+    }
+    out.push(0x00);
+}
+
+/// Value of a key which consists of the type byte only.
+fn map_get(map: &Map, key_type: u8) -> Option<&[u8]> {
+    map.iter()
+        .find(|(key, _)| key.as_slice() == [key_type])
+        .map(|(_, value)| value.as_slice())
+}
+
+impl Psbt {
+    /// Parses a base64 encoded PSBT and checks its inputs for consistency.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let data = BASE64_STANDARD
+            .decode(input.trim())
+            .map_err(|_| "not valid base64".to_string())?;
+        let mut reader = Reader {
+            data: data.strip_prefix(MAGIC).ok_or("missing PSBT magic bytes")?,
+        };
+
+        let global = read_map(&mut reader)?;
+        if let Some(version) = map_get(&global, GLOBAL_VERSION) {
+            if version != [0, 0, 0, 0] {
+                return Err("only PSBT version 0 is supported".to_string());
+            }
+        }
+        let tx =
+            Tx::parse(map_get(&global, GLOBAL_UNSIGNED_TX).ok_or("missing unsigned transaction")?)?;
+        if tx.inputs.iter().any(|input| !input.script_sig.is_empty()) {
+            return Err("unsigned transaction has signature scripts".to_string());
+        }
+        let inputs = (0..tx.inputs.len())
+            .map(|_| read_map(&mut reader))
+            .collect::<Result<Vec<_>, String>>()?;
+        let outputs = (0..tx.outputs.len())
+            .map(|_| read_map(&mut reader))
+            .collect::<Result<Vec<_>, String>>()?;
+        if !reader.is_empty() {
+            return Err("trailing data after PSBT".to_string());
+        }
+
+        let psbt = Psbt {
+            tx,
+            global,
+            inputs,
+            outputs,
+        };
+        for index in 0..psbt.inputs.len() {
+            psbt.spent_output(index)
+                .map_err(|err| format!("input {index}: {err}"))?;
+            psbt.sighash_type(index)
+                .map_err(|err| format!("input {index}: {err}"))?;
+        }
+        Ok(psbt)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_map(&mut out, &self.global);
+        for map in self.inputs.iter().chain(&self.outputs) {
+            write_map(&mut out, map);
+        }
+        out
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.serialize())
+    }
+
+    /// Output spent by the input, `None` if the PSBT doesn't include it.
+    fn spent_output(&self, index: usize) -> Result<Option<TxOut>, String> {
+        let map = &self.inputs[index];
+        let outpoint = &self.tx.inputs[index];
+
+        let witness_utxo = map_get(map, IN_WITNESS_UTXO)
+            .map(|value| {
+                let mut reader = Reader { data: value };
+                let output = TxOut::read(&mut reader)?;
+                match reader.is_empty() {
+                    true => Ok(output),
+                    false => Err("invalid witness UTXO".to_string()),
+                }
+            })
+            .transpose()?;
+        let non_witness_utxo = map_get(map, IN_NON_WITNESS_UTXO)
+            .map(|value| {
+                let prev_tx = Tx::parse(value)?;
+                if prev_tx.txid() != outpoint.prev_txid {
+                    return Err("previous transaction doesn't match the outpoint".to_string());
+                }
+                prev_tx
+                    .outputs
+                    .get(outpoint.prev_vout as usize)
+                    .cloned()
+                    .ok_or("previous transaction has no such output".to_string())
+            })
+            .transpose()?;
+
+        match (witness_utxo, non_witness_utxo) {
+            (Some(witness), Some(full)) if witness != full => {
+                Err("witness UTXO doesn't match the previous transaction".to_string())
+            }
+            (_, Some(full)) => Ok(Some(full)),
+            (Some(witness), None) if is_p2pkh_script(&witness.script_pubkey) => {
+                Err("P2PKH inputs need the previous transaction".to_string())
+            }
+            (Some(witness), None) => Ok(Some(witness)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether the input includes the previous transaction, its txid is
+    /// checked against the outpoint when parsing.
+    fn has_previous_tx(&self, index: usize) -> bool {
+        map_get(&self.inputs[index], IN_NON_WITNESS_UTXO).is_some()
+    }
+
+    fn sighash_type(&self, index: usize) -> Result<u32, String> {
+        let Some(value) = map_get(&self.inputs[index], IN_SIGHASH_TYPE) else {
+            return Ok(SIGHASH_ALL);
+        };
+        let sighash_type =
+            u32::from_le_bytes(value.try_into().map_err(|_| "invalid sighash type")?);
+        match sighash_type & !SIGHASH_ANYONECANPAY {
+            SIGHASH_ALL | SIGHASH_NONE | SIGHASH_SINGLE => Ok(sighash_type),
+            _ => Err(format!("unsupported sighash type {sighash_type:#x}")),
+        }
+    }
+
+    fn is_finalized(&self, index: usize) -> bool {
+        let map = &self.inputs[index];
+        map_get(map, IN_FINAL_SCRIPTSIG).is_some() || map_get(map, IN_FINAL_SCRIPTWITNESS).is_some()
+    }
+
+    /// Sighash of the input for the key and the sighash type, `None` if the
+    /// input doesn't pay to the key or can't be signed anymore. Legacy
+    /// outputs of uncompressed keys are only matched when `compressed` is false.
+    pub fn sighash(
+        &self,
+        index: usize,
+        key: &VerifyingKey,
+        compressed: bool,
+    ) -> Option<([u8; 32], u32)> {
+        if self.is_finalized(index) {
+            return None;
+        }
+        let spent = self.spent_output(index).ok()??;
+        let sighash_type = self.sighash_type(index).ok()?;
+
+        if compressed && spent.script_pubkey == p2wpkh_script(key) {
+            let script_code = p2pkh_script(key, true);
+            self.segwit_v0_sighash(index, &script_code, spent.value, sighash_type)
+        } else if spent.script_pubkey == p2pkh_script(key, compressed) {
+            self.legacy_sighash(index, &spent.script_pubkey, sighash_type)
+        } else {
+            None
+        }
+        .map(|hash| (hash, sighash_type))
+    }
+
+    /// Original sighash algorithm, `SIGHASH_SINGLE` without a matching
+    /// output is refused.
+    fn legacy_sighash(
+        &self,
+        index: usize,
+        script_code: &[u8],
+        sighash_type: u32,
+    ) -> Option<[u8; 32]> {
+        let base_type = sighash_type & !SIGHASH_ANYONECANPAY;
+        let mut tx = self.tx.clone();
+        for (i, input) in tx.inputs.iter_mut().enumerate() {
+            input.script_sig = match i == index {
+                true => script_code.to_vec(),
+                false => Vec::new(),
+            };
+            if i != index && base_type != SIGHASH_ALL {
+                input.sequence = 0;
+            }
+        }
+        match base_type {
+            SIGHASH_NONE => tx.outputs.clear(),
+            SIGHASH_SINGLE => {
+                if index >= tx.outputs.len() {
+                    return None;
+                }
+                tx.outputs.truncate(index + 1);
+                for output in &mut tx.outputs[..index] {
+                    output.value = u64::MAX;
+                    output.script_pubkey.clear();
+                }
+            }
+            _ => {}
+        }
+        if sighash_type & SIGHASH_ANYONECANPAY != 0 {
+            tx.inputs = vec![tx.inputs.swap_remove(index)];
+        }
+
+        let mut data = tx.serialize();
+        data.extend_from_slice(&sighash_type.to_le_bytes());
+        Some(sha256d(&data))
+    }
+
+    /// Sighash of segwit version 0 inputs, see BIP-143.
+    fn segwit_v0_sighash(
+        &self,
+        index: usize,
+        script_code: &[u8],
+        value: u64,
+        sighash_type: u32,
+    ) -> Option<[u8; 32]> {
+        let base_type = sighash_type & !SIGHASH_ANYONECANPAY;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let input = &self.tx.inputs[index];
+
+        let hash_prevouts = match anyone_can_pay {
+            true => [0; 32],
+            false => sha256d(
+                &self
+                    .tx
+                    .inputs
+                    .iter()
+                    .flat_map(|input| {
+                        [input.prev_txid.as_slice(), &input.prev_vout.to_le_bytes()].concat()
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        let hash_sequence = match anyone_can_pay || base_type != SIGHASH_ALL {
+            true => [0; 32],
+            false => sha256d(
+                &self
+                    .tx
+                    .inputs
+                    .iter()
+                    .flat_map(|input| input.sequence.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        let hash_outputs = match base_type {
+            SIGHASH_ALL => {
+                let mut outputs = Vec::new();
+                self.tx
+                    .outputs
+                    .iter()
+                    .for_each(|output| output.write(&mut outputs));
+                sha256d(&outputs)
+            }
+            SIGHASH_SINGLE => {
+                let mut output = Vec::new();
+                self.tx.outputs.get(index)?.write(&mut output);
+                sha256d(&output)
+            }
+            _ => [0; 32],
+        };
+
+        let mut data = self.tx.version.to_le_bytes().to_vec();
+        data.extend_from_slice(&hash_prevouts);
+        data.extend_from_slice(&hash_sequence);
+        data.extend_from_slice(&input.prev_txid);
+        data.extend_from_slice(&input.prev_vout.to_le_bytes());
+        write_var_bytes(&mut data, script_code);
+        data.extend_from_slice(&value.to_le_bytes());
+        data.extend_from_slice(&input.sequence.to_le_bytes());
+        data.extend_from_slice(&hash_outputs);
+        data.extend_from_slice(&self.tx.lock_time.to_le_bytes());
+        data.extend_from_slice(&sighash_type.to_le_bytes());
+        Some(sha256d(&data))
+    }
+
+    /// Adds partial signatures of the key to all inputs paying to it and
+    /// returns how many were signed.
+    ///
+    /// Every input of the key needs its previous transaction: the BIP-143
+    /// sighash only commits to the amount of the signed input, so a PSBT
+    /// with witness UTXOs alone can understate amounts across signing
+    /// rounds and pay them as fee (CVE-2020-14199).
+    pub fn sign(&mut self, signing_key: &SigningKey) -> Result<usize, String> {
+        let verifying_key = signing_key.verifying_key();
+        let public_key = verifying_key.to_encoded_point(true);
+        let public_key = public_key.as_bytes();
+
+        if let Some(index) = (0..self.inputs.len()).find(|&index| {
+            self.sighash(index, verifying_key, true).is_some() && !self.has_previous_tx(index)
+        }) {
+            return Err(format!(
+                "input {index}: the previous transaction is needed to verify the amount"
+            ));
+        }
+
+        let mut signed = 0;
+        for index in 0..self.inputs.len() {
+            let Some((sighash, sighash_type)) = self.sighash(index, verifying_key, true) else {
+                continue;
+            };
+            let signature: Signature = signing_key
+                .sign_prehash(&sighash)
+                .map_err(|_| "signing failed".to_string())?;
+            // BIP-146, high-S signatures are non-standard
+            let signature = signature.normalize_s().unwrap_or(signature);
+            let mut value = signature.to_der().as_bytes().to_vec();
+            value.push(sighash_type as u8);
+
+            let key = [&[IN_PARTIAL_SIG], public_key].concat();
+            let map = &mut self.inputs[index];
+            map.retain(|(existing, _)| *existing != key);
+            map.push((key, value));
+            signed += 1;
+        }
+        Ok(signed)
+    }
+
+    pub fn partial_signatures(&self) -> Vec<PartialSignature> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .flat_map(|(input, map)| {
+                map.iter()
+                    .filter(|(key, _)| key.first() == Some(&IN_PARTIAL_SIG))
+                    .map(move |(key, value)| PartialSignature {
+                        input,
+                        public_key: key[1..].to_vec(),
+                        signature: value.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Inputs for review, `public_key` is the SEC1 key they are checked against.
+    pub fn input_views(&self, public_key: &[u8]) -> Vec<InputView> {
+        let key = VerifyingKey::from_sec1_bytes(public_key).ok();
+        self.tx
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let spent = self.spent_output(index).ok().flatten();
+                InputView {
+                    outpoint: format!("{}:{}", format_txid(&input.prev_txid), input.prev_vout),
+                    address: spent
+                        .as_ref()
+                        .map(|output| format_script(&output.script_pubkey)),
+                    value: spent.map(|output| output.value),
+                    value_verified: self.has_previous_tx(index),
+                    signable: key.is_some_and(|key| {
+                        self.sighash(index, &key, public_key.len() == 33).is_some()
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    pub fn output_views(&self) -> Vec<OutputView> {
+        self.tx
+            .outputs
+            .iter()
+            .map(|output| OutputView {
+                address: format_script(&output.script_pubkey),
+                value: output.value,
+            })
+            .collect()
+    }
+
+    /// Whether the amounts of all inputs come from their previous
+    /// transactions, otherwise the fee can't be trusted.
+    pub fn fee_verified(&self) -> bool {
+        (0..self.inputs.len()).all(|index| self.has_previous_tx(index))
+    }
+
+    /// Fee in satoshis, `None` if an input amount is unknown or the outputs
+    /// spend more than the inputs.
+    pub fn fee(&self) -> Option<u64> {
+        let mut inputs = 0u64;
+        for index in 0..self.inputs.len() {
+            let output = self.spent_output(index).ok()??;
+            inputs = inputs.checked_add(output.value)?;
+        }
+        let outputs = self
+            .tx
+            .outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))?;
+        inputs.checked_sub(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::signature::hazmat::PrehashVerifier;
+
+    /// Unsigned transaction of the native P2WPKH example of BIP-143, input 1
+    /// spends 6 BTC from the P2WPKH output of `BIP143_KEY`.
+    const BIP143_UNSIGNED_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+    const BIP143_KEY: &str = "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9";
+    const BIP143_SCRIPT: &str = "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1";
+    const BIP143_VALUE: u64 = 600_000_000;
+    const BIP143_SIGHASH: &str = "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670";
+    /// The same transaction signed, in the segwit serialization and without
+    /// witnesses.
+    const BIP143_SIGNED_TX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+    const BIP143_STRIPPED_TX: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+    const BIP143_TXID: &str = "e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609";
+
+    /// Mainnet transaction spending the P2PKH output
+    /// d1c789a9c60383bf715f3f6ad9d14b91fe55f3deb369fe5d9280cb1a01793f81:0.
+    const P2PKH_SIGNED_TX: &str = "0100000001813f79011acb80925dfe69b3def355fe914bd1d96a3f5f71bf8303c6a989c7d1000000006b483045022100ed81ff192e75a3fd2304004dcadb746fa5e24c5031ccfcf21320b0277457c98f02207a986d955c6e0cb35d446a89d3f56100f4d7f67801c31967743a9c8e10615bed01210349fc4e631e3624a545de3f89f5d8684c7b8138bd94bdd531d2e213bf016b278afeffffff02a135ef01000000001976a914bc3b654dca7e56b04dca18f2566cdaf02e8d9ada88ac99c39800000000001976a9141c4bc762dd5423e332166702cb75f40df79fea1288ac19430600";
+    const P2PKH_SCRIPT: &str = "76a914a802fc56c704ce87c42d7c92eb75e7896bdc41ae88ac";
+    const P2PKH_SIGHASH: &str = "27e0c5994dec7824e56dec6b2fcb342eb7cdb0d0957c2fce9882f715e85d81a6";
+
+    fn unhex(data: &str) -> Vec<u8> {
+        hex::decode(data).unwrap()
+    }
+
+    /// PSBT of an unsigned transaction with the given input maps.
+    fn psbt(tx: Tx, inputs: Vec<Map>) -> Psbt {
+        Psbt {
+            global: vec![(vec![GLOBAL_UNSIGNED_TX], tx.serialize())],
+            outputs: vec![Vec::new(); tx.outputs.len()],
+            inputs,
+            tx,
+        }
+    }
+
+    fn witness_utxo(value: u64, script_pubkey: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let mut output = Vec::new();
+        TxOut {
+            value,
+            script_pubkey,
+        }
+        .write(&mut output);
+        (vec![IN_WITNESS_UTXO], output)
+    }
+
+    fn bip143_psbt() -> Psbt {
+        let tx = Tx::parse(&unhex(BIP143_UNSIGNED_TX)).unwrap();
+        let inputs = vec![
+            Vec::new(),
+            vec![witness_utxo(BIP143_VALUE, unhex(BIP143_SCRIPT))],
+        ];
+        psbt(tx, inputs)
+    }
+
+    fn bip143_key() -> SigningKey {
+        SigningKey::from_slice(&unhex(BIP143_KEY)).unwrap()
+    }
+
+    #[test]
+    fn bip143_p2wpkh_sighash() {
+        let psbt = bip143_psbt();
+        let key = bip143_key();
+        let (sighash, sighash_type) = psbt.sighash(1, key.verifying_key(), true).unwrap();
+        assert_eq!(hex::encode(sighash), BIP143_SIGHASH);
+        assert_eq!(sighash_type, SIGHASH_ALL);
+
+        // P2WPKH outputs only pay to compressed keys
+        assert!(psbt.sighash(1, key.verifying_key(), false).is_none());
+        assert!(psbt.sighash(0, key.verifying_key(), true).is_none());
+    }
+
+    #[test]
+    fn legacy_p2pkh_sighash() {
+        let signed = Tx::parse(&unhex(P2PKH_SIGNED_TX)).unwrap();
+        // script signature is <signature with sighash type> <public key>
+        let script_sig = &signed.inputs[0].script_sig;
+        let der = &script_sig[1..script_sig[0] as usize];
+        let public_key = &script_sig[script_sig[0] as usize + 2..];
+        let verifying_key = VerifyingKey::from_sec1_bytes(public_key).unwrap();
+        assert_eq!(p2pkh_script(&verifying_key, true), unhex(P2PKH_SCRIPT));
+
+        let mut tx = signed.clone();
+        tx.inputs[0].script_sig.clear();
+        let psbt = psbt(tx, vec![Vec::new()]);
+        let sighash = psbt
+            .legacy_sighash(0, &unhex(P2PKH_SCRIPT), SIGHASH_ALL)
+            .unwrap();
+        assert_eq!(hex::encode(sighash), P2PKH_SIGHASH);
+        verifying_key
+            .verify_prehash(&sighash, &Signature::from_der(der).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let legacy = unhex(P2PKH_SIGNED_TX);
+        assert_eq!(Tx::parse(&legacy).unwrap().serialize(), legacy);
+
+        // witnesses are skipped, the txid doesn't commit to them
+        let segwit = Tx::parse(&unhex(BIP143_SIGNED_TX)).unwrap();
+        assert_eq!(segwit.serialize(), unhex(BIP143_STRIPPED_TX));
+        assert_eq!(Tx::parse(&unhex(BIP143_STRIPPED_TX)).unwrap(), segwit);
+        assert_eq!(format_txid(&segwit.txid()), BIP143_TXID);
+
+        let mut trailing = legacy.clone();
+        trailing.push(0);
+        assert!(Tx::parse(&trailing).is_err());
+        assert!(Tx::parse(&legacy[..legacy.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn sign() {
+        let key = bip143_key();
+        // previous transaction paying to the key, so the amount is verified
+        let prev_tx = Tx {
+            version: 2,
+            inputs: vec![TxIn {
+                prev_txid: [7; 32],
+                prev_vout: 0,
+                script_sig: Vec::new(),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![TxOut {
+                value: BIP143_VALUE,
+                script_pubkey: unhex(BIP143_SCRIPT),
+            }],
+            lock_time: 0,
+        };
+        let mut tx = Tx::parse(&unhex(BIP143_UNSIGNED_TX)).unwrap();
+        tx.inputs[1].prev_txid = prev_tx.txid();
+        tx.inputs[1].prev_vout = 0;
+        let mut psbt = psbt(
+            tx,
+            vec![
+                Vec::new(),
+                vec![(vec![IN_NON_WITNESS_UTXO], prev_tx.serialize())],
+            ],
+        );
+
+        assert_eq!(psbt.sign(&key).unwrap(), 1);
+        let signatures = psbt.partial_signatures();
+        assert_eq!(signatures.len(), 1);
+        let partial = &signatures[0];
+        assert_eq!(partial.input, 1);
+        let (sighash, _) = psbt.sighash(1, key.verifying_key(), true).unwrap();
+        let (&sighash_type, der) = partial.signature.split_last().unwrap();
+        assert_eq!(sighash_type as u32, SIGHASH_ALL);
+        let signature = Signature::from_der(der).unwrap();
+        assert!(signature.normalize_s().is_none(), "signature is low-S");
+        key.verifying_key()
+            .verify_prehash(&sighash, &signature)
+            .unwrap();
+
+        // the signed PSBT parses back with the signature
+        let parsed = Psbt::parse(&psbt.to_base64()).unwrap();
+        assert_eq!(parsed.partial_signatures()[0].signature, partial.signature);
+    }
+
+    #[test]
+    fn sign_needs_previous_tx() {
+        let mut psbt = bip143_psbt();
+        let err = psbt.sign(&bip143_key()).unwrap_err();
+        assert!(err.starts_with("input 1:"), "{err}");
+        assert!(psbt.partial_signatures().is_empty());
+        assert!(!psbt.fee_verified());
+    }
+
+    #[test]
+    fn sighash_single_without_output() {
+        let key = bip143_key();
+        let mut psbt = bip143_psbt();
+        psbt.tx.outputs.truncate(1);
+        psbt.inputs[1].push((vec![IN_SIGHASH_TYPE], SIGHASH_SINGLE.to_le_bytes().to_vec()));
+        assert_eq!(psbt.sighash_type(1).unwrap(), SIGHASH_SINGLE);
+
+        assert!(psbt.sighash(1, key.verifying_key(), true).is_none());
+        assert_eq!(psbt.sign(&key).unwrap(), 0);
+        assert!(psbt
+            .legacy_sighash(1, &p2pkh_script(key.verifying_key(), true), SIGHASH_SINGLE)
+            .is_none());
+        assert!(psbt
+            .segwit_v0_sighash(1, &[], BIP143_VALUE, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY)
+            .is_none());
+        // input 0 still has its output
+        assert!(psbt
+            .legacy_sighash(0, &p2pkh_script(key.verifying_key(), true), SIGHASH_SINGLE)
+            .is_some());
+    }
+}
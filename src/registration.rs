@@ -0,0 +1,281 @@
+use poem::{http::StatusCode, Error};
+use rand_core::{OsRng, RngCore};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+use super::db::{DbError, SharedStorage, UserId};
+use super::password::{check_password_policy, PasswordHashing};
+use super::token::hash_token;
+
+const INVITE_CODE_LEN: usize = 16;
+
+/// Who may create accounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RegistrationMode {
+    /// Anyone can register.
+    Open,
+    /// Registration needs an invite code created with `waas create-invite`.
+    Invite,
+    /// Only the administrator creates accounts with `waas add-user`.
+    #[default]
+    Admin,
+}
+
+impl std::str::FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(RegistrationMode::Open),
+            "invite" => Ok(RegistrationMode::Invite),
+            "admin" => Ok(RegistrationMode::Admin),
+            _ => Err(format!("Unknown registration mode '{s}'")),
+        }
+    }
+}
+
+/// Usernames are shown in HTML views and used to log in: 3 to 32 letters,
+/// digits, `-`, `_` or `.`, starting with a letter or digit.
+pub fn is_valid_username(username: &str) -> bool {
+    (3..=32).contains(&username.len())
+        && username.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Generates a single-use invite code, only its hash is stored.
+pub fn generate_invite_code() -> String {
+    let mut bytes = [0u8; INVITE_CODE_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Creates an account after checking the username and password policy,
+/// consuming the invite code when one is given.
+pub async fn create_user(
+    username: &str,
+    password: &str,
+    invite_code: Option<&str>,
+    db: &SharedStorage,
+    passwords: &Arc<PasswordHashing>,
+) -> poem::Result<UserId> {
+    if !is_valid_username(username) {
+        return Err(Error::from_string(
+            "Username must have 3 to 32 letters, digits, '-', '_' or '.' and start with a letter or digit",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    check_password_policy(username, password)
+        .map_err(|err| Error::from_string(err, StatusCode::BAD_REQUEST))?;
+
+    // hashing is slow on purpose, keep it off the async runtime
+    let hashing = passwords.clone();
+    let password = Zeroizing::new(password.to_string());
+    let password_hash = tokio::task::spawn_blocking(move || hashing.hash(&password))
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|res| res)
+        .map_err(|err| Error::from_string(err, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let invite_hash = invite_code.map(|code| hash_token(code.trim()));
+    db.add_user(username, &password_hash, invite_hash.as_deref())
+        .await
+        .map_err(|err| {
+            let status = match err {
+                DbError::UserAlreadyExists => StatusCode::CONFLICT,
+                DbError::InviteNotFound => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Error::from_string(err.to_string(), status)
+        })
+}
+
+/// Self-registration of a visitor, allowed depending on the registration mode.
+pub async fn register_user(
+    mode: RegistrationMode,
+    username: &str,
+    password: &str,
+    invite_code: Option<&str>,
+    db: &SharedStorage,
+    passwords: &Arc<PasswordHashing>,
+) -> poem::Result<UserId> {
+    let invite_code = match mode {
+        RegistrationMode::Open => None,
+        RegistrationMode::Invite => Some(
+            invite_code
+                .filter(|code| !code.trim().is_empty())
+                .ok_or_else(|| {
+                    Error::from_string("Registration needs an invite code", StatusCode::FORBIDDEN)
+                })?,
+        ),
+        RegistrationMode::Admin => {
+            return Err(Error::from_string(
+                "Registration is closed, accounts are created by the administrator",
+                StatusCode::FORBIDDEN,
+            ))
+        }
+    };
+
+    create_user(username, password, invite_code, db, passwords).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemDb;
+    use crate::password::PasswordConfig;
+
+    fn storage() -> SharedStorage {
+        SharedStorage::new(MemDb::new())
+    }
+
+    /// Cheap Argon2id parameters, the hashing itself is tested in `password`.
+    fn passwords() -> Arc<PasswordHashing> {
+        Arc::new(
+            PasswordHashing::new(PasswordConfig {
+                argon2_memory_kib: 64,
+                argon2_iterations: 1,
+                ..PasswordConfig::default()
+            })
+            .unwrap(),
+        )
+    }
+
+    fn status(result: poem::Result<UserId>) -> StatusCode {
+        result.unwrap_err().status()
+    }
+
+    #[tokio::test]
+    async fn create_and_duplicate() {
+        let db = storage();
+        let passwords = passwords();
+        let user_id = create_user("alice", "correct horse", None, &db, &passwords)
+            .await
+            .unwrap();
+        let (stored_id, hash) = db.get_user_password_hash("alice").await.unwrap();
+        assert_eq!(stored_id, user_id);
+        assert!(passwords.verify("correct horse", &hash));
+
+        assert_eq!(
+            status(create_user("alice", "battery staple", None, &db, &passwords).await),
+            StatusCode::CONFLICT
+        );
+        let bob = create_user("bob", "battery staple", None, &db, &passwords)
+            .await
+            .unwrap();
+        assert_ne!(bob, user_id);
+    }
+
+    #[tokio::test]
+    async fn invalid_username() {
+        let db = storage();
+        let passwords = passwords();
+        for username in [
+            "",
+            "al",
+            &"a".repeat(33),
+            "-alice",
+            ".alice",
+            "al ice",
+            "alice<b>",
+            "élise",
+        ] {
+            assert!(!is_valid_username(username), "{username}");
+            assert_eq!(
+                status(create_user(username, "correct horse", None, &db, &passwords).await),
+                StatusCode::BAD_REQUEST,
+                "{username}"
+            );
+        }
+        for username in ["abc", "Alice.B-2_x", &"a".repeat(32)] {
+            assert!(is_valid_username(username), "{username}");
+        }
+    }
+
+    #[tokio::test]
+    async fn password_policy() {
+        let db = storage();
+        let passwords = passwords();
+        for password in ["short", "alice123", "ALICE123", &"x".repeat(129)] {
+            assert_eq!(
+                status(create_user("alice123", password, None, &db, &passwords).await),
+                StatusCode::BAD_REQUEST,
+                "{password}"
+            );
+        }
+        assert!(matches!(
+            db.get_user_password_hash("alice123").await,
+            Err(DbError::UserNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn invite_code() {
+        let db = storage();
+        let passwords = passwords();
+        let code = generate_invite_code();
+        db.add_invite(&hash_token(&code)).await.unwrap();
+
+        assert_eq!(
+            status(create_user("alice", "correct horse", Some("wrong"), &db, &passwords).await),
+            StatusCode::FORBIDDEN
+        );
+        // the code is trimmed like when it is pasted
+        create_user(
+            "alice",
+            "correct horse",
+            Some(&format!(" {code}\n")),
+            &db,
+            &passwords,
+        )
+        .await
+        .unwrap();
+        // and used up
+        assert_eq!(
+            status(create_user("bob", "correct horse", Some(&code), &db, &passwords).await),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn registration_modes() {
+        let db = storage();
+        let passwords = passwords();
+        let code = generate_invite_code();
+        db.add_invite(&hash_token(&code)).await.unwrap();
+
+        let register = |mode, username, invite_code| {
+            let db = db.clone();
+            let passwords = passwords.clone();
+            async move {
+                register_user(
+                    mode,
+                    username,
+                    "correct horse",
+                    invite_code,
+                    &db,
+                    &passwords,
+                )
+                .await
+            }
+        };
+        assert_eq!(
+            status(register(RegistrationMode::Admin, "alice", Some(code.as_str())).await),
+            StatusCode::FORBIDDEN
+        );
+        for invite_code in [None, Some(""), Some("  ")] {
+            assert_eq!(
+                status(register(RegistrationMode::Invite, "alice", invite_code).await),
+                StatusCode::FORBIDDEN
+            );
+        }
+        register(RegistrationMode::Invite, "alice", Some(code.as_str()))
+            .await
+            .unwrap();
+        // open registration ignores invite codes
+        register(RegistrationMode::Open, "bob", Some("wrong"))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use poem::{
+    http::{header, StatusCode},
+    Endpoint, Error, Middleware, Request, Result,
+};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use super::db::{KeyId, SharedStorage, UserId};
+
+const TOKEN_PREFIX: &str = "waas_";
+const TOKEN_LEN: usize = 32;
+
+/// Permission granted to an API token.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// `read:public-keys`, list keys of the user and their public keys.
+    ReadPublicKeys,
+    /// `sign:<key-id>` signs with a single key, `sign:*` with any key of the user.
+    Sign(Option<KeyId>),
+}
+
+impl Scope {
+    /// Whether a token with this scope may do what `required` guards.
+    pub fn allows(&self, required: &Scope) -> bool {
+        match (self, required) {
+            (Scope::Sign(None), Scope::Sign(_)) => true,
+            _ => self == required,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::ReadPublicKeys => write!(f, "read:public-keys"),
+            Scope::Sign(None) => write!(f, "sign:*"),
+            Scope::Sign(Some(key_id)) => write!(f, "sign:{key_id}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "read:public-keys" => Ok(Scope::ReadPublicKeys),
+            "sign:*" => Ok(Scope::Sign(None)),
+            _ => s
+                .strip_prefix("sign:")
+                .and_then(|key_id| key_id.parse().ok())
+                .map(|key_id| Scope::Sign(Some(key_id)))
+                .ok_or_else(|| format!("Unknown scope '{s}'")),
+        }
+    }
+}
+
+/// Parses a space or comma separated list of scopes, at least one is required.
+pub fn parse_scopes(input: &str) -> std::result::Result<Vec<Scope>, String> {
+    let mut scopes = Vec::new();
+    for scope in input.split([' ', ',']).filter(|s| !s.is_empty()) {
+        let scope = scope.parse()?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_string());
+    }
+    Ok(scopes)
+}
+
+/// Generates a new random token, only its hash is ever stored.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Tokens are random and long, a plain SHA-256 is enough to protect them at rest.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Client authenticated with an API token, set on the request by [`BearerAuth`].
+#[derive(Clone, Debug)]
+pub struct TokenAuth {
+    pub user_id: UserId,
+    pub scopes: Vec<Scope>,
+}
+
+impl TokenAuth {
+    pub fn allows(&self, required: &Scope) -> bool {
+        self.scopes.iter().any(|scope| scope.allows(required))
+    }
+}
+
+/// Middleware checking `Authorization: Bearer <token>` headers.
+///
+/// Requests without the header are passed on unchanged, requests with a
+/// valid token get a [`TokenAuth`] extension and the rest are rejected.
+pub struct BearerAuth;
+
+impl<E: Endpoint> Middleware<E> for BearerAuth {
+    type Output = BearerAuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        BearerAuthEndpoint { inner: ep }
+    }
+}
+
+pub struct BearerAuthEndpoint<E> {
+    inner: E,
+}
+
+fn invalid_token() -> Error {
+    Error::from_string("Invalid API token", StatusCode::UNAUTHORIZED)
+}
+
+impl<E: Endpoint> Endpoint for BearerAuthEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if let Some(value) = req.headers().get(header::AUTHORIZATION) {
+            let token = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(invalid_token)?
+                .trim()
+                .to_string();
+            let db = req
+                .data::<SharedStorage>()
+                .ok_or_else(|| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))?
+                .clone();
+
+            let auth = {
+                let (user_id, info) = db
+                    .find_api_token(&hash_token(&token))
+                    .await
+                    .map_err(|_| invalid_token())?;
+                if let Err(err) = db.touch_api_token(info.id).await {
+                    tracing::warn!("Can't update last use of API token {}: {err}", info.id);
+                }
+                TokenAuth {
+                    user_id,
+                    scopes: info.scopes.iter().filter_map(|s| s.parse().ok()).collect(),
+                }
+            };
+            req.extensions_mut().insert(auth);
+        }
+
+        self.inner.call(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{handler, EndpointExt};
+
+    use super::*;
+    use crate::db::{MemDb, Storage};
+
+    #[test]
+    fn scope_parse() {
+        for (input, scope) in [
+            ("read:public-keys", Scope::ReadPublicKeys),
+            ("sign:7", Scope::Sign(Some(7))),
+            ("sign:*", Scope::Sign(None)),
+        ] {
+            assert_eq!(input.parse::<Scope>(), Ok(scope));
+            assert_eq!(scope.to_string(), input);
+        }
+        for input in ["", "sign:", "sign:abc", "sign:-1", "read:keys", "Sign:1"] {
+            assert!(input.parse::<Scope>().is_err(), "{input}");
+        }
+
+        assert_eq!(
+            parse_scopes("sign:1, read:public-keys sign:1"),
+            Ok(vec![Scope::Sign(Some(1)), Scope::ReadPublicKeys])
+        );
+        assert!(parse_scopes(" , ").is_err());
+        assert!(parse_scopes("sign:1 write:keys").is_err());
+    }
+
+    #[test]
+    fn scope_allows() {
+        let single = TokenAuth {
+            user_id: 1,
+            scopes: vec![Scope::Sign(Some(1))],
+        };
+        assert!(single.allows(&Scope::Sign(Some(1))));
+        assert!(!single.allows(&Scope::Sign(Some(2))));
+        assert!(!single.allows(&Scope::ReadPublicKeys));
+
+        let any = TokenAuth {
+            user_id: 1,
+            scopes: vec![Scope::Sign(None)],
+        };
+        assert!(any.allows(&Scope::Sign(Some(1))));
+        assert!(any.allows(&Scope::Sign(Some(2))));
+        assert!(!any.allows(&Scope::ReadPublicKeys));
+
+        // a token for a single key never allows signing with all of them
+        assert!(!Scope::Sign(Some(1)).allows(&Scope::Sign(None)));
+    }
+
+    #[handler]
+    fn scopes(req: &Request) -> String {
+        match req.extensions().get::<TokenAuth>() {
+            Some(auth) => auth
+                .scopes
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => "none".to_string(),
+        }
+    }
+
+    async fn call(ep: &impl Endpoint, authorization: Option<&str>) -> Result<String> {
+        let mut req = Request::builder();
+        if let Some(value) = authorization {
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        let resp = ep.get_response(req.finish()).await;
+        if !resp.status().is_success() {
+            return Err(Error::from_status(resp.status()));
+        }
+        Ok(resp.into_body().into_string().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn bearer_auth() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 2 * TOKEN_LEN);
+        assert_ne!(generate_token(), token);
+
+        let mut db = MemDb::new();
+        let token_id = db
+            .add_api_token(1, "ci", &hash_token(&token), &["sign:1".to_string()])
+            .unwrap();
+        let db = SharedStorage::new(db);
+        let ep = scopes.with(BearerAuth).data(db.clone());
+
+        assert_eq!(call(&ep, None).await.unwrap(), "none");
+        assert_eq!(
+            call(&ep, Some(&format!("Bearer {token}"))).await.unwrap(),
+            "sign:1"
+        );
+        assert!(db.list_api_tokens(1).await.unwrap()[0]
+            .last_used_at
+            .is_some());
+
+        for value in [
+            token.clone(),
+            format!("Basic {token}"),
+            "Bearer waas_00".to_string(),
+        ] {
+            let err = call(&ep, Some(&value)).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        db.revoke_api_token(1, token_id).await.unwrap();
+        let err = call(&ep, Some(&format!("Bearer {token}")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+}
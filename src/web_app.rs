@@ -1,3 +1,4 @@
+use base64::prelude::*;
 use futures_util::stream;
 use poem::{
     get, handler,
@@ -5,25 +6,63 @@ use poem::{
     post,
     session::Session,
     web::sse::{Event, SSE},
-    web::{Data, Form, Html, Path},
+    web::{Data, Form, Html, Json, Multipart, Path, Query},
     Error, IntoResponse, Response, Route,
 };
-use pwhash::bcrypt::*;
 use rand::Rng;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
+use zeroize::Zeroizing;
 
-use super::db::{MemDb, UserId};
-use super::service::SignService;
+use super::api::{self, SignJob};
+use super::db::{ApiTokenInfo, DbError, KeyId, KeyInfo, SharedStorage, TokenId, UserId};
+use super::eip712::{FieldView, TypedData};
+use super::keyring::{Keyring, SealedKey};
+use super::keystore::{Kdf, KeyExportPolicy};
+use super::password::{check_password_policy, PasswordHashing};
+use super::psbt::{format_btc, Psbt};
+use super::registration::{register_user, RegistrationMode};
+use super::service::{
+    check_derivation_path, check_message, check_sign_options, digest_reader, BitcoinAddressType,
+    DerivedAddress, HashFunction, MessageEncoding, MessageHash, PublicKeyInfo, SignOptions,
+    SignService, SignServiceError, SignatureBundle, SignatureEncoding, SignatureScheme,
+    KEY_ALGORITHM_BIP32, KEY_ALGORITHM_SECP256K1,
+};
 use super::template::*;
+use super::token::{generate_token, hash_token, parse_scopes, Scope};
 
 #[derive(Default)]
 pub struct WebApp {
     // Map of currently logged users and cookie session
     current_users: HashMap<String, UserId>,
-    pending_messages: HashMap<UserId, String>,
-    signed_messages: HashMap<UserId, String>,
+    pending_messages: HashMap<UserId, PendingMessage>,
+    signed_messages: HashMap<UserId, SignedMessage>,
+    // Signing jobs started through the JSON API
+    pub(crate) sign_jobs: HashMap<String, SignJob>,
+}
+
+struct PendingMessage {
+    key_id: KeyId,
+    derivation_path: Option<String>,
+    scheme: SignatureScheme,
+    options: SignOptions,
+    message: String,
+    file: Option<SignedFile>,
+}
+
+struct SignedMessage {
+    key_id: KeyId,
+    derivation_path: Option<String>,
+    scheme: SignatureScheme,
+    options: SignOptions,
+    bundle: SignatureBundle,
+}
+
+/// Uploaded file whose digest is signed.
+struct SignedFile {
+    filename: String,
+    size: u64,
 }
 
 #[derive(Deserialize)]
@@ -32,17 +71,866 @@ struct LoginParams {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct RegisterParams {
+    username: String,
+    password: String,
+    password_confirm: String,
+    invite_code: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct SignMessageParams {
+    key_id: KeyId,
+    derivation_path: Option<String>,
+    #[serde(default)]
+    scheme: SignatureScheme,
+    /// Empty for the encoding of the scheme.
+    encoding: Option<String>,
+    #[serde(default)]
+    hash: HashFunction,
+    #[serde(default)]
+    address_type: BitcoinAddressType,
+    #[serde(default)]
+    prehashed: bool,
+    #[serde(default)]
+    message_encoding: MessageEncoding,
+    message: String,
+}
+
+/// Fields of the file signing form, they come before the file so it can be
+/// hashed while it is uploaded.
+#[derive(Default)]
+struct SignFileParams {
+    key_id: Option<KeyId>,
+    derivation_path: Option<String>,
+    scheme: SignatureScheme,
+    hash: HashFunction,
+    encoding: Option<SignatureEncoding>,
+}
+
+#[derive(Deserialize)]
+struct TypedDataParams {
+    key_id: KeyId,
+    derivation_path: Option<String>,
+    typed_data: String,
+}
+
+#[derive(Deserialize)]
+struct PsbtParams {
+    key_id: KeyId,
+    derivation_path: Option<String>,
+    psbt: String,
+}
+
+#[derive(Deserialize)]
+struct VerifyParams {
+    #[serde(default)]
+    scheme: SignatureScheme,
+    message: String,
+    signature: String,
+    public_key: String,
+    #[serde(default)]
+    hash: HashFunction,
+    #[serde(default)]
+    prehashed: bool,
+    #[serde(default)]
+    message_encoding: MessageEncoding,
+}
+
+#[derive(Deserialize)]
+struct VerifyBundleParams {
+    bundle: String,
+    /// Empty to verify the digest of the bundle.
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    message_encoding: MessageEncoding,
+}
+
+#[derive(Deserialize)]
+struct RecoverParams {
+    scheme: SignatureScheme,
     message: String,
+    signature: String,
+    #[serde(default)]
+    hash: HashFunction,
+    #[serde(default)]
+    prehashed: bool,
+    #[serde(default)]
+    message_encoding: MessageEncoding,
+}
+
+#[derive(Serialize)]
+struct VerifyResult {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GenerateKeyParams {
+    label: String,
+    /// Set by the checkbox when it is ticked.
+    non_exportable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportKeyParams {
+    label: String,
+    key: String,
+    password: Option<String>,
+    non_exportable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportKeyParams {
+    password: String,
+    keystore_password: String,
+    keystore_password_confirm: String,
+    kdf: String,
+}
+
+#[derive(Deserialize)]
+struct HdKeyParams {
+    label: String,
+    mnemonic: Option<String>,
+    #[serde(default)]
+    passphrase: String,
+}
+
+#[derive(Deserialize)]
+struct CreateTokenParams {
+    name: String,
+    scopes: String,
+}
+
+/// Key labels and token names are shown in HTML views, only a safe set of
+/// characters is allowed.
+pub(crate) fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.chars().count() <= 32
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+}
+
+/// Escapes user provided text shown in HTML views. Braces are escaped too,
+/// so the text can't be mistaken for a template placeholder.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '{' => escaped.push_str("&#123;"),
+            '}' => escaped.push_str("&#125;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn key_options(keys: &[KeyInfo]) -> String {
+    keys.iter()
+        .map(|key| {
+            HTML_KEY_OPTION
+                .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+                .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+        })
+        .collect()
+}
+
+fn public_key_html(key: &KeyInfo, public_key: &PublicKeyInfo) -> String {
+    HTML_PUBLIC_KEY
+        .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+        .replace(HTML_KEY_PUBLIC_ID_PLACEHOLDER, &key.public_id)
+        .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+        .replace(
+            HTML_PUBLIC_KEY_TABLE_PLACEHOLDER,
+            &public_key_table(public_key),
+        )
+}
+
+fn public_key_table(public_key: &PublicKeyInfo) -> String {
+    HTML_PUBLIC_KEY_TABLE
+        .replace(
+            HTML_PUBLIC_KEY_COMPRESSED_PLACEHOLDER,
+            &public_key.compressed,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_UNCOMPRESSED_PLACEHOLDER,
+            &public_key.uncompressed,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_BASE64_PLACEHOLDER,
+            &public_key.compressed_base64,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_ETHEREUM_PLACEHOLDER,
+            &public_key.ethereum_address,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_BITCOIN_P2PKH_PLACEHOLDER,
+            &public_key.bitcoin_p2pkh,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_BITCOIN_P2WPKH_PLACEHOLDER,
+            &public_key.bitcoin_p2wpkh,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_BITCOIN_P2SH_P2WPKH_PLACEHOLDER,
+            &public_key.bitcoin_p2sh_p2wpkh,
+        )
+        .replace(
+            HTML_PUBLIC_KEY_BITCOIN_P2TR_PLACEHOLDER,
+            &public_key.bitcoin_p2tr,
+        )
+        .replace(HTML_PUBLIC_KEY_X_ONLY_PLACEHOLDER, &public_key.x_only)
+        .replace(
+            HTML_PUBLIC_KEY_NOSTR_NPUB_PLACEHOLDER,
+            &public_key.nostr_npub,
+        )
+}
+
+fn scheme_options(schemes: impl Iterator<Item = SignatureScheme>) -> String {
+    schemes
+        .map(|scheme| {
+            HTML_SCHEME_OPTION
+                .replace(HTML_SCHEME_PLACEHOLDER, scheme.as_str())
+                .replace(HTML_SCHEME_TITLE_PLACEHOLDER, scheme.title())
+        })
+        .collect()
+}
+
+fn encoding_options() -> String {
+    SignatureEncoding::ALL
+        .iter()
+        .map(|encoding| {
+            HTML_ENCODING_OPTION
+                .replace(HTML_ENCODING_PLACEHOLDER, encoding.as_str())
+                .replace(HTML_ENCODING_TITLE_PLACEHOLDER, encoding.title())
+        })
+        .collect()
+}
+
+/// Encoding of the signature if it isn't the one of the scheme and whether it is low-S.
+fn signature_encoding_html(scheme: SignatureScheme, encoding: Option<SignatureEncoding>) -> String {
+    let mut html = encoding
+        .map(|encoding| {
+            HTML_SIGNATURE_ENCODING.replace(HTML_ENCODING_TITLE_PLACEHOLDER, encoding.title())
+        })
+        .unwrap_or_default();
+    if !matches!(
+        scheme,
+        SignatureScheme::Schnorr | SignatureScheme::NostrEvent
+    ) {
+        html.push_str(HTML_SIGNATURE_LOW_S);
+    }
+    html
+}
+
+fn hash_options() -> String {
+    HashFunction::ALL
+        .iter()
+        .map(|hash| {
+            HTML_HASH_OPTION
+                .replace(HTML_HASH_PLACEHOLDER, hash.as_str())
+                .replace(HTML_HASH_TITLE_PLACEHOLDER, hash.title())
+        })
+        .collect()
+}
+
+fn address_type_options() -> String {
+    BitcoinAddressType::ALL
+        .iter()
+        .map(|address_type| {
+            HTML_ADDRESS_TYPE_OPTION
+                .replace(HTML_ADDRESS_TYPE_PLACEHOLDER, address_type.as_str())
+                .replace(HTML_ADDRESS_TYPE_TITLE_PLACEHOLDER, address_type.title())
+        })
+        .collect()
+}
+
+fn hash_fields() -> String {
+    HTML_HASH_FIELDS.replace(HTML_HASH_OPTIONS_PLACEHOLDER, &hash_options())
+}
+
+/// The digest which was signed and how it was computed, for verifiers which only get the digest.
+fn signed_digest_html(signed: &SignedMessage) -> String {
+    let Some(digest) = &signed.bundle.digest else {
+        return String::new();
+    };
+    let hash = signed.options.hash;
+    let how = if signed.options.encoding == Some(SignatureEncoding::Jws) {
+        ", SHA-256 of the JWS signing input".to_string()
+    } else if !signed.scheme.hashes_message() {
+        String::new()
+    } else if signed.bundle.filename.is_some() {
+        format!(", {} of the file", hash.function.title())
+    } else if hash.prehashed {
+        format!(", given pre-hashed with {}", hash.function.title())
+    } else {
+        format!(", {} of the message", hash.function.title())
+    };
+    HTML_SIGNED_DIGEST
+        .replace(HTML_DIGEST_PLACEHOLDER, digest)
+        .replace(HTML_DIGEST_HASH_PLACEHOLDER, &how)
+}
+
+/// Name and size of a signed file.
+fn signed_file_html(bundle: &SignatureBundle) -> String {
+    let (Some(filename), Some(size)) = (&bundle.filename, bundle.size) else {
+        return String::new();
+    };
+    HTML_SIGNED_FILE
+        .replace(HTML_FILENAME_PLACEHOLDER, &escape_html(filename))
+        .replace(HTML_FILE_SIZE_PLACEHOLDER, &size.to_string())
+}
+
+/// Link to download the signature bundle, named after the file for files.
+fn signature_bundle_html(bundle: &SignatureBundle) -> String {
+    let json = serde_json::to_string_pretty(bundle).unwrap_or_default();
+    let filename = format!(
+        "{}.sig.json",
+        bundle.filename.as_deref().unwrap_or("signature")
+    );
+    HTML_SIGNATURE_BUNDLE
+        .replace(HTML_BUNDLE_FILENAME_PLACEHOLDER, &escape_html(&filename))
+        .replace(
+            HTML_SIGNATURE_BUNDLE_PLACEHOLDER,
+            &BASE64_STANDARD.encode(json),
+        )
+}
+
+fn typed_data_rows(fields: &[FieldView]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            HTML_TYPED_DATA_ROW
+                .replace(HTML_FIELD_DEPTH_PLACEHOLDER, &(field.depth + 1).to_string())
+                .replace(HTML_FIELD_NAME_PLACEHOLDER, &escape_html(&field.name))
+                .replace(HTML_FIELD_TYPE_PLACEHOLDER, &escape_html(&field.type_name))
+                .replace(
+                    HTML_FIELD_VALUE_PLACEHOLDER,
+                    &field.value.as_deref().map(escape_html).unwrap_or_default(),
+                )
+        })
+        .collect()
+}
+
+/// Renders the typed data for review, the signing form carries the JSON as entered.
+fn typed_data_preview(
+    key: &KeyInfo,
+    derivation_path: Option<&str>,
+    json: &str,
+) -> Result<String, String> {
+    let typed_data = TypedData::parse(json)?;
+    let domain = typed_data.domain_view()?;
+    let message = typed_data.message_view()?;
+
+    Ok(HTML_BODY_CONTENT_TYPED_DATA_PREVIEW
+        .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+        .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+        .replace(
+            HTML_DERIVATION_PATH_PLACEHOLDER,
+            &escape_html(derivation_path.unwrap_or_default()),
+        )
+        .replace(
+            HTML_TYPED_DATA_DOMAIN_SEPARATOR_PLACEHOLDER,
+            &format!("0x{}", hex::encode(typed_data.domain_separator()?)),
+        )
+        .replace(
+            HTML_TYPED_DATA_STRUCT_HASH_PLACEHOLDER,
+            &format!("0x{}", hex::encode(typed_data.struct_hash()?)),
+        )
+        .replace(
+            HTML_TYPED_DATA_DIGEST_PLACEHOLDER,
+            &format!("0x{}", hex::encode(typed_data.signing_hash()?)),
+        )
+        .replace(
+            HTML_TYPED_DATA_PRIMARY_TYPE_PLACEHOLDER,
+            &escape_html(&typed_data.primary_type),
+        )
+        .replace(
+            HTML_TYPED_DATA_DOMAIN_ROWS_PLACEHOLDER,
+            &typed_data_rows(&domain),
+        )
+        .replace(
+            HTML_TYPED_DATA_MESSAGE_ROWS_PLACEHOLDER,
+            &typed_data_rows(&message),
+        )
+        .replace(HTML_TYPED_DATA_PLACEHOLDER, &escape_html(json)))
+}
+
+/// Renders inputs, outputs and fee of the PSBT for review, inputs signed with
+/// the public key are tagged.
+fn psbt_preview(
+    key: &KeyInfo,
+    derivation_path: Option<&str>,
+    public_key: &[u8],
+    input: &str,
+) -> Result<String, String> {
+    let psbt = Psbt::parse(input)?;
+    let inputs = psbt.input_views(public_key);
+
+    let input_rows: String = inputs
+        .iter()
+        .map(|input| {
+            HTML_PSBT_INPUT_ROW
+                .replace(HTML_PSBT_OUTPOINT_PLACEHOLDER, &input.outpoint)
+                .replace(
+                    HTML_PSBT_ADDRESS_PLACEHOLDER,
+                    input.address.as_deref().unwrap_or("unknown"),
+                )
+                .replace(
+                    HTML_PSBT_AMOUNT_PLACEHOLDER,
+                    &input.value.map(format_btc).unwrap_or_default(),
+                )
+                .replace(
+                    HTML_PSBT_INPUT_TAG_PLACEHOLDER,
+                    &format!(
+                        "{}{}",
+                        if input.signable {
+                            HTML_PSBT_INPUT_TAG_SIGNED
+                        } else {
+                            ""
+                        },
+                        if input.value.is_some() && !input.value_verified {
+                            HTML_PSBT_INPUT_TAG_UNVERIFIED
+                        } else {
+                            ""
+                        },
+                    ),
+                )
+        })
+        .collect();
+    let output_rows: String = psbt
+        .output_views()
+        .iter()
+        .map(|output| {
+            HTML_PSBT_OUTPUT_ROW
+                .replace(HTML_PSBT_ADDRESS_PLACEHOLDER, &output.address)
+                .replace(HTML_PSBT_AMOUNT_PLACEHOLDER, &format_btc(output.value))
+        })
+        .collect();
+    let sign_form = if !inputs.iter().any(|input| input.signable) {
+        HTML_PSBT_NOTHING_TO_SIGN
+    } else if inputs
+        .iter()
+        .any(|input| input.signable && !input.value_verified)
+    {
+        HTML_PSBT_PREVIOUS_TX_MISSING
+    } else {
+        HTML_PSBT_SIGN_FORM
+    };
+    let mut fee = psbt.fee().map(format_btc).unwrap_or("unknown".to_string());
+    if !psbt.fee_verified() {
+        fee.push_str(HTML_PSBT_FEE_UNVERIFIED);
+    }
+
+    Ok(HTML_BODY_CONTENT_PSBT_PREVIEW
+        .replace(HTML_PSBT_INPUT_ROWS_PLACEHOLDER, &input_rows)
+        .replace(HTML_PSBT_OUTPUT_ROWS_PLACEHOLDER, &output_rows)
+        .replace(HTML_PSBT_FEE_PLACEHOLDER, &fee)
+        .replace(HTML_PSBT_SIGN_FORM_PLACEHOLDER, sign_form)
+        .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+        .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+        .replace(
+            HTML_DERIVATION_PATH_PLACEHOLDER,
+            &escape_html(derivation_path.unwrap_or_default()),
+        )
+        .replace(HTML_PSBT_PLACEHOLDER, &escape_html(input.trim())))
+}
+
+/// Derives public key of the stored key, the private key has to be unsealed for that.
+/// HD keys give the child key at the derivation path or their master key.
+pub(crate) async fn derive_public_key(
+    user_id: UserId,
+    key: &KeyInfo,
+    sealed_key: &SealedKey,
+    derivation_path: Option<&str>,
+    keyring: &Keyring,
+    sign_service: &Mutex<SignService>,
+) -> Option<PublicKeyInfo> {
+    let secret = keyring.open(user_id, key, sealed_key).ok()?;
+    let sign_service = sign_service.lock().await;
+    let child_key = sign_service
+        .child_key(&key.algorithm, &secret, derivation_path)
+        .ok()?;
+    sign_service.public_key(&child_key).ok()
+}
+
+/// First addresses of the standard accounts of an HD key, none for other keys.
+pub(crate) async fn derive_addresses(
+    user_id: UserId,
+    key: &KeyInfo,
+    sealed_key: &SealedKey,
+    keyring: &Keyring,
+    sign_service: &Mutex<SignService>,
+) -> Vec<DerivedAddress> {
+    if key.algorithm != KEY_ALGORITHM_BIP32 {
+        return Vec::new();
+    }
+    match keyring.open(user_id, key, sealed_key) {
+        Ok(seed) => sign_service
+            .lock()
+            .await
+            .derived_addresses(&seed)
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Empty derivation path fields of forms mean no path.
+fn derivation_path(input: &Option<String>) -> Option<&str> {
+    input
+        .as_deref()
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+}
+
+/// Seals the key under the current KEK and stores it for the user.
+async fn store_user_key(
+    user_id: UserId,
+    label: &str,
+    algorithm: &str,
+    key: &[u8],
+    exportable: bool,
+    db: &SharedStorage,
+    keyring: &Arc<Keyring>,
+) -> poem::Result<KeyId> {
+    let keyring = keyring.clone();
+    let sealed_algorithm = algorithm.to_string();
+    let key = Zeroizing::new(key.to_vec());
+    db.add_user_key(user_id, label, algorithm, exportable, move |key_id| {
+        keyring
+            .seal(user_id, key_id, &sealed_algorithm, &key)
+            .map_err(|err| err.to_string())
+    })
+    .await
+    .map_err(|err| {
+        let status = match err {
+            DbError::KeyAlreadyExists => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Error::from_string(err.to_string(), status)
+    })
+}
+
+fn check_label(label: &str) -> poem::Result<()> {
+    if !is_valid_label(label) {
+        return Err(Error::from_string(
+            "Key label must have up to 32 letters, digits, spaces, '-', '_' or '.'",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    Ok(())
+}
+
+/// Generates a new key for the user and stores it sealed under the current KEK.
+pub(crate) async fn generate_user_key(
+    user_id: UserId,
+    label: &str,
+    exportable: bool,
+    db: &SharedStorage,
+    keyring: &Arc<Keyring>,
+    sign_service: &Mutex<SignService>,
+) -> poem::Result<KeyId> {
+    check_label(label)?;
+    let key = sign_service.lock().await.generate_key();
+    store_user_key(
+        user_id,
+        label,
+        KEY_ALGORITHM_SECP256K1,
+        &key,
+        exportable,
+        db,
+        keyring,
+    )
+    .await
+}
+
+/// Private key to import, see [`SignService::import_key`] for the accepted
+/// encodings.
+pub(crate) struct KeyImport<'a> {
+    pub label: &'a str,
+    pub key: &'a str,
+    /// Decrypts encrypted PEM and keystore files.
+    pub password: Option<&'a str>,
+    pub exportable: bool,
+}
+
+/// Imports an existing private key for the user.
+pub(crate) async fn import_user_key(
+    user_id: UserId,
+    import: KeyImport<'_>,
+    db: &SharedStorage,
+    keyring: &Arc<Keyring>,
+) -> poem::Result<KeyId> {
+    check_label(import.label)?;
+    // keystore files are encrypted under a slow KDF, keep it off the async runtime
+    // and don't hold the sign service while it runs
+    let key = Zeroizing::new(import.key.to_string());
+    let password = import
+        .password
+        .map(|password| Zeroizing::new(password.to_string()));
+    let key = tokio::task::spawn_blocking(move || {
+        SignService::import_key(&key, password.as_deref().map(String::as_str))
+    })
+    .await
+    .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?
+    .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+    store_user_key(
+        user_id,
+        import.label,
+        KEY_ALGORITHM_SECP256K1,
+        &key,
+        import.exportable,
+        db,
+        keyring,
+    )
+    .await
+}
+
+/// Stores an HD key of the given mnemonic, or of a new one which is
+/// returned as it is the only backup of the key.
+pub(crate) async fn add_hd_user_key(
+    user_id: UserId,
+    label: &str,
+    mnemonic: Option<&str>,
+    passphrase: &str,
+    db: &SharedStorage,
+    keyring: &Arc<Keyring>,
+    sign_service: &Mutex<SignService>,
+) -> poem::Result<(KeyId, Option<Zeroizing<String>>)> {
+    check_label(label)?;
+    let (mnemonic, generated) = match mnemonic {
+        Some(mnemonic) => (Zeroizing::new(mnemonic.to_string()), false),
+        None => (
+            Zeroizing::new(sign_service.lock().await.generate_mnemonic()),
+            true,
+        ),
+    };
+    let seed = sign_service
+        .lock()
+        .await
+        .hd_seed(&mnemonic, passphrase)
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+
+    let key_id = store_user_key(
+        user_id,
+        label,
+        KEY_ALGORITHM_BIP32,
+        &seed,
+        true,
+        db,
+        keyring,
+    )
+    .await?;
+    Ok((key_id, generated.then_some(mnemonic)))
+}
+
+/// Export of a key to a keystore v3 file.
+pub(crate) struct KeyExport<'a> {
+    pub key_id: KeyId,
+    /// Password of the account, checked again before the key leaves waas.
+    pub password: &'a str,
+    /// Password the keystore file is encrypted under.
+    pub keystore_password: &'a str,
+    pub kdf: Kdf,
+}
+
+/// Whether the key can be exported, HD keys are backed up by their mnemonic.
+pub(crate) fn check_key_export(policy: KeyExportPolicy, key: &KeyInfo) -> poem::Result<()> {
+    if policy == KeyExportPolicy::Disabled {
+        return Err(Error::from_string(
+            "Key export is disabled on this server",
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    if !key.exportable {
+        return Err(Error::from_string(
+            "Key is marked non-exportable",
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    if key.algorithm != KEY_ALGORITHM_SECP256K1 {
+        return Err(Error::from_string(
+            "Only secp256k1 keys can be exported, HD keys are backed up by their mnemonic",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    Ok(())
+}
+
+/// Encrypts the key of the user into a keystore v3 file after checking the
+/// password of the account again.
+pub(crate) async fn export_user_key(
+    user_id: UserId,
+    export: KeyExport<'_>,
+    policy: KeyExportPolicy,
+    db: &SharedStorage,
+    keyring: &Keyring,
+    passwords: &Arc<PasswordHashing>,
+) -> poem::Result<(KeyInfo, String)> {
+    let username = db.get_user_name(user_id).await.unwrap_or_default();
+    match authenticate(&username, export.password, db, passwords).await {
+        Ok(authenticated) if authenticated == user_id => {}
+        _ => {
+            return Err(Error::from_string(
+                "Wrong password",
+                StatusCode::UNAUTHORIZED,
+            ))
+        }
+    }
+
+    let key = db.get_user_key(user_id, export.key_id).await;
+    let (key, sealed_key) =
+        key.map_err(|_| Error::from_string("User doesn't have such key", StatusCode::NOT_FOUND))?;
+    check_key_export(policy, &key)?;
+    check_password_policy(&username, export.keystore_password).map_err(|err| {
+        Error::from_string(format!("Keystore password: {err}"), StatusCode::BAD_REQUEST)
+    })?;
+    let private_key = keyring
+        .open(user_id, &key, &sealed_key)
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    // the KDF is slow on purpose, keep it off the async runtime and don't hold
+    // the sign service while it runs
+    let keystore_password = Zeroizing::new(export.keystore_password.to_string());
+    let kdf = export.kdf;
+    let keystore = tokio::task::spawn_blocking(move || {
+        SignService::export_keystore(&private_key, &keystore_password, kdf)
+    })
+    .await
+    .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?
+    .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((key, keystore))
+}
+
+/// Checks the password of the user. A stored hash made with outdated
+/// parameters is replaced by a new one while the password is at hand.
+pub(crate) async fn authenticate(
+    username: &str,
+    password: &str,
+    db: &SharedStorage,
+    passwords: &Arc<PasswordHashing>,
+) -> Result<UserId, DbError> {
+    let user = db.get_user_password_hash(username).await;
+    let (user_id, hash) = match user {
+        Ok((user_id, hash)) => (Some(user_id), hash),
+        // unknown users take as long as a wrong password so they can't be told apart
+        Err(DbError::UserNotFound) => (None, passwords.dummy_hash().to_string()),
+        Err(err) => return Err(err),
+    };
+
+    // hashing is slow on purpose, keep it off the async runtime
+    let passwords = passwords.clone();
+    let password = Zeroizing::new(password.to_string());
+    let (valid, rehashed) = tokio::task::spawn_blocking(move || {
+        if !passwords.verify(&password, &hash) {
+            return (false, None);
+        }
+        let rehashed = if passwords.needs_rehash(&hash) {
+            passwords.hash(&password).ok()
+        } else {
+            None
+        };
+        (true, rehashed)
+    })
+    .await
+    .unwrap_or((false, None));
+
+    let Some(user_id) = user_id else {
+        return Err(DbError::UserNotFound);
+    };
+    if !valid {
+        return Err(DbError::WrongPassword);
+    }
+    if let Some(hash) = rehashed {
+        if let Err(err) = db.set_user_password_hash(user_id, &hash).await {
+            tracing::warn!("Can't update password hash of user {user_id}: {err}");
+        }
+    }
+    Ok(user_id)
+}
+
+/// Export actions of the key, none for HD keys.
+fn key_actions(key: &KeyInfo, policy: KeyExportPolicy) -> String {
+    if !key.exportable {
+        HTML_KEY_NON_EXPORTABLE_TAG.to_string()
+    } else if key.algorithm != KEY_ALGORITHM_SECP256K1 {
+        String::new()
+    } else if policy == KeyExportPolicy::Disabled {
+        HTML_KEY_LOCK_EXPORT_BUTTON.to_string()
+    } else {
+        format!("{HTML_KEY_EXPORT_BUTTON}{HTML_KEY_LOCK_EXPORT_BUTTON}")
+    }
+}
+
+fn key_rows(keys: &[KeyInfo], policy: KeyExportPolicy) -> String {
+    keys.iter()
+        .map(|key| {
+            HTML_KEY_ROW
+                .replace(HTML_KEY_ACTIONS_PLACEHOLDER, &key_actions(key, policy))
+                .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+                .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+                .replace(HTML_KEY_ALGORITHM_PLACEHOLDER, &key.algorithm)
+                .replace(
+                    HTML_KEY_CREATED_PLACEHOLDER,
+                    &format_timestamp(key.created_at),
+                )
+        })
+        .collect()
+}
+
+/// Addresses of the accounts of an HD key, shown below the key list.
+fn derived_addresses_html(key: &KeyInfo, addresses: &[DerivedAddress]) -> String {
+    let rows: String = addresses
+        .iter()
+        .map(|address| {
+            HTML_DERIVED_ADDRESS_ROW
+                .replace(HTML_DERIVED_ACCOUNT_PLACEHOLDER, &address.account)
+                .replace(HTML_DERIVATION_PATH_PLACEHOLDER, &address.derivation_path)
+                .replace(HTML_DERIVED_ADDRESS_PLACEHOLDER, &address.address)
+        })
+        .collect();
+    HTML_DERIVED_ADDRESSES
+        .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+        .replace(HTML_DERIVED_ADDRESS_ROWS_PLACEHOLDER, &rows)
+}
+
+/// Menu items of pages for visitors who are not logged in.
+fn visitor_menu_items(mode: RegistrationMode) -> String {
+    if mode == RegistrationMode::Admin {
+        HTML_NAVBAR_MENU_ITEM_VERIFY.to_string()
+    } else {
+        format!(
+            "{}{}",
+            HTML_NAVBAR_MENU_ITEM_VERIFY, HTML_NAVBAR_MENU_ITEM_REGISTER
+        )
+    }
 }
 
 #[handler]
-fn view_login() -> impl IntoResponse {
+fn view_login(Data(mode): Data<&RegistrationMode>) -> impl IntoResponse {
     Html(format!(
         "{}{}{}{}",
         HTML_HEAD,
-        HTML_BODY_NAVBAR.replace(HTML_NAVBAR_MENU_ITEM_PLACEHOLDER, ""),
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &visitor_menu_items(*mode)
+        ),
         HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, HTML_BODY_CONTENT_LOGIN),
         HTML_BODY_FOOTER
     ))
@@ -53,32 +941,16 @@ async fn view_login_validate(
     Form(params): Form<LoginParams>,
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
+    passwords: Data<&Arc<PasswordHashing>>,
 ) -> impl IntoResponse {
-    let pass_hash = WebApp::hash_password(&params.password);
-
-    if let Some(pass_hash) = pass_hash {
-        if let Ok(user_id) = db
-            .lock()
-            .await
-            .validate_user_password(&params.username, &pass_hash)
-        {
-            let user_session: String = (0..16)
-                .map(|_| char::from(rand::thread_rng().gen_range(32..127)))
-                .collect();
-            session.set("user_session", &user_session);
-
-            state
-                .lock()
-                .await
-                .current_users
-                .insert(user_session, user_id);
+    if let Ok(user_id) = authenticate(&params.username, &params.password, &db, &passwords).await {
+        state.lock().await.start_session(session, user_id);
 
-            return Response::builder()
-                .status(StatusCode::FOUND)
-                .header(header::LOCATION, "/")
-                .finish();
-        }
+        return Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, "/")
+            .finish();
     }
 
     Html(format!(
@@ -94,12 +966,87 @@ async fn view_login_validate(
     .into_response()
 }
 
+#[handler]
+fn view_register(Data(mode): Data<&RegistrationMode>) -> impl IntoResponse {
+    let content = match *mode {
+        RegistrationMode::Open => {
+            HTML_BODY_CONTENT_REGISTER.replace(HTML_INVITE_FIELD_PLACEHOLDER, "")
+        }
+        RegistrationMode::Invite => HTML_BODY_CONTENT_REGISTER
+            .replace(HTML_INVITE_FIELD_PLACEHOLDER, HTML_REGISTER_INVITE_FIELD),
+        RegistrationMode::Admin => HTML_BODY_CONTENT_REGISTRATION_CLOSED.to_string(),
+    };
+
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                HTML_NAVBAR_MENU_ITEM_LOGIN, HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &content),
+        HTML_BODY_FOOTER
+    ))
+}
+
+#[handler]
+async fn view_register_submit(
+    Form(params): Form<RegisterParams>,
+    Data(mode): Data<&RegistrationMode>,
+    db: Data<&SharedStorage>,
+    passwords: Data<&Arc<PasswordHashing>>,
+) -> impl IntoResponse {
+    if params.password != params.password_confirm {
+        return custom_error(Error::from_string(
+            "Passwords don't match",
+            StatusCode::BAD_REQUEST,
+        ))
+        .await
+        .into_response();
+    }
+
+    let username = params.username.trim();
+    if let Err(err) = register_user(
+        *mode,
+        username,
+        &params.password,
+        params.invite_code.as_deref(),
+        &db,
+        &passwords,
+    )
+    .await
+    {
+        return custom_error(err).await.into_response();
+    }
+
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                HTML_NAVBAR_MENU_ITEM_LOGIN, HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(
+            HTML_BODY_CONTENT_PLACEHOLDER,
+            &HTML_BODY_CONTENT_REGISTERED.replace(HTML_USERNAME_PLACEHOLDER, username)
+        ),
+        HTML_BODY_FOOTER
+    ))
+    .into_response()
+}
+
 #[handler]
 async fn view_sign_message(
     Form(params): Form<SignMessageParams>,
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
 ) -> impl IntoResponse {
     let mut state = state.lock().await;
 
@@ -114,10 +1061,64 @@ async fn view_sign_message(
                 .into_response();
             }
 
-            if db.lock().await.get_user_key(*user_id).is_ok() {
+            let encoding = match params
+                .encoding
+                .as_deref()
+                .filter(|encoding| !encoding.is_empty())
+            {
+                Some(encoding) => match encoding.parse::<SignatureEncoding>() {
+                    Ok(encoding) => Some(encoding),
+                    Err(err) => {
+                        return custom_error(Error::from_string(err, StatusCode::BAD_REQUEST))
+                            .await
+                            .into_response();
+                    }
+                },
+                None => None,
+            };
+            let options = SignOptions {
+                hash: MessageHash {
+                    function: params.hash,
+                    prehashed: params.prehashed,
+                    encoding: params.message_encoding,
+                },
+                encoding,
+                address_type: params.address_type,
+            };
+            if let Err(err) = check_message(params.scheme, &params.message)
+                .and_then(|_| check_sign_options(params.scheme, &options, &params.message))
+            {
+                return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                    .await
+                    .into_response();
+            }
+
+            let derivation_path = derivation_path(&params.derivation_path).map(str::to_string);
+            let key = db.get_user_key(*user_id, params.key_id).await;
+            if let Ok((key, _)) = key {
+                if let Err(err) = check_derivation_path(&key.algorithm, derivation_path.as_deref())
+                {
+                    return custom_error(Error::from_string(
+                        err.to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                    .await
+                    .into_response();
+                }
+
                 let user_id = *user_id;
-                state.pending_messages.insert(user_id, params.message);
-                let username = db.lock().await.get_user_name(user_id).unwrap_or_default();
+                state.pending_messages.insert(
+                    user_id,
+                    PendingMessage {
+                        key_id: params.key_id,
+                        derivation_path,
+                        scheme: params.scheme,
+                        options,
+                        message: params.message,
+                        file: None,
+                    },
+                );
+                let username = db.get_user_name(user_id).await.unwrap_or_default();
 
                 Html(format!(
                     "{}{}{}{}{}",
@@ -128,7 +1129,7 @@ async fn view_sign_message(
                             "{}{}",
                             HTML_NAVBAR_MENU_ITEM_LOGOUT
                                 .replace(HTML_USERNAME_PLACEHOLDER, &username),
-                            HTML_NAVBAR_MENU_ITEM_DISCARD_KEY
+                            HTML_NAVBAR_MENU_ITEM_KEYS
                         )
                     ),
                     HTML_BODY_CONTENT.replace(
@@ -163,15 +1164,31 @@ async fn view_sign_message(
 async fn view_message_signed(
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
 ) -> impl IntoResponse {
     let mut state = state.lock().await;
 
     if let Some(user_session) = session.get::<String>("user_session") {
         if let Some(user_id) = state.current_users.get(&user_session) {
             let user_id = *user_id;
-            if let Some(msg) = state.signed_messages.remove(&user_id) {
-                let username = db.lock().await.get_user_name(user_id).unwrap_or_default();
+            if let Some(signed) = state.signed_messages.remove(&user_id) {
+                let username = db.get_user_name(user_id).await.unwrap_or_default();
+                let key = db.get_key(signed.key_id).await;
+                let public_key = match key {
+                    Ok((_, key_info, sealed_key)) => derive_public_key(
+                        user_id,
+                        &key_info,
+                        &sealed_key,
+                        signed.derivation_path.as_deref(),
+                        &keyring,
+                        &sign_service,
+                    )
+                    .await
+                    .map(|public_key| public_key_html(&key_info, &public_key)),
+                    Err(_) => None,
+                };
 
                 Html(format!(
                     "{}{}{}{}",
@@ -183,24 +1200,1095 @@ async fn view_message_signed(
                             HTML_NAVBAR_MENU_ITEM_LOGOUT
                                 .replace(HTML_USERNAME_PLACEHOLDER, &username),
                             HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
-                            HTML_NAVBAR_MENU_ITEM_DISCARD_KEY,
+                            HTML_NAVBAR_MENU_ITEM_KEYS,
+                        )
+                    ),
+                    HTML_BODY_CONTENT.replace(
+                        HTML_BODY_CONTENT_PLACEHOLDER,
+                        &HTML_BODY_CONTENT_MESSAGE_SIGNED
+                            .replace(HTML_SCHEME_TITLE_PLACEHOLDER, signed.scheme.title())
+                            .replace(
+                                HTML_BODY_CONTENT_INTERNAL_PLACEHOLDER,
+                                &signed.bundle.signature
+                            )
+                            .replace(
+                                HTML_SIGNATURE_ENCODING_PLACEHOLDER,
+                                &signature_encoding_html(signed.scheme, signed.options.encoding)
+                            )
+                            .replace(HTML_SIGNED_DIGEST_PLACEHOLDER, &signed_digest_html(&signed))
+                            .replace(
+                                HTML_SIGNED_FILE_PLACEHOLDER,
+                                &signed_file_html(&signed.bundle)
+                            )
+                            .replace(
+                                HTML_SIGNATURE_BUNDLE_PLACEHOLDER,
+                                &signature_bundle_html(&signed.bundle)
+                            )
+                            .replace(
+                                HTML_PUBLIC_KEYS_PLACEHOLDER,
+                                &public_key.unwrap_or("Key was discarded".to_string())
+                            )
+                    ),
+                    HTML_BODY_FOOTER
+                ))
+                .into_response()
+            } else {
+                custom_error(Error::from_string(
+                    "User doesn't have any signed messages",
+                    StatusCode::NOT_FOUND,
+                ))
+                .await
+                .into_response()
+            }
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_sign_file(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+
+            let body_content = if keys.is_empty() {
+                HTML_BODY_CONTENT_NO_KEY.replace(HTML_USERNAME_PLACEHOLDER, &username)
+            } else {
+                HTML_BODY_CONTENT_SIGN_FILE
+                    .replace(HTML_KEY_OPTIONS_PLACEHOLDER, &key_options(&keys))
+                    .replace(
+                        HTML_SCHEME_OPTIONS_PLACEHOLDER,
+                        &scheme_options(
+                            SignatureScheme::ALL
+                                .iter()
+                                .copied()
+                                .filter(SignatureScheme::hashes_message),
+                        ),
+                    )
+                    .replace(HTML_HASH_OPTIONS_PLACEHOLDER, &hash_options())
+                    .replace(HTML_ENCODING_OPTIONS_PLACEHOLDER, &encoding_options())
+            };
+
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+/// Reads the fields of the file signing form and hashes the file as it is
+/// uploaded, it is never held in memory. The file is the last field, the
+/// others are needed before it is hashed and can't come after it.
+async fn read_sign_file_form(
+    mut multipart: Multipart,
+) -> Result<(SignFileParams, SignedFile, [u8; 32]), Error> {
+    let bad_request = |err: String| Error::from_string(err, StatusCode::BAD_REQUEST);
+    let mut params = SignFileParams::default();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            if params.key_id.is_none() {
+                return Err(bad_request("file must be the last field".to_string()));
+            }
+            // Some browsers send the path of the file.
+            let filename = field
+                .file_name()
+                .and_then(|name| name.rsplit(['/', '\\']).next())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("file")
+                .to_string();
+            let (size, digest) = digest_reader(params.hash, field.into_async_read())
+                .await
+                .map_err(|err| bad_request(err.to_string()))?;
+            if multipart.next_field().await?.is_some() {
+                return Err(bad_request("file must be the last field".to_string()));
+            }
+            return Ok((params, SignedFile { filename, size }, digest));
+        }
+
+        let value = field
+            .text()
+            .await
+            .map_err(|err| bad_request(err.to_string()))?;
+        let value = value.trim();
+        match name.as_str() {
+            "key_id" => {
+                params.key_id = Some(
+                    value
+                        .parse()
+                        .map_err(|_| bad_request(format!("Invalid key id '{value}'")))?,
+                )
+            }
+            "derivation_path" if !value.is_empty() => {
+                params.derivation_path = Some(value.to_string())
+            }
+            "scheme" => params.scheme = value.parse().map_err(bad_request)?,
+            "hash" => params.hash = value.parse().map_err(bad_request)?,
+            "encoding" if !value.is_empty() => {
+                params.encoding = Some(value.parse().map_err(bad_request)?)
+            }
+            _ => {}
+        }
+    }
+    Err(bad_request("No file was uploaded".to_string()))
+}
+
+#[handler]
+async fn view_sign_file_upload(
+    multipart: Multipart,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        let user_id = state.lock().await.current_users.get(&user_session).copied();
+        if let Some(user_id) = user_id {
+            let (params, file, digest) = match read_sign_file_form(multipart).await {
+                Ok(form) => form,
+                Err(err) => return custom_error(err).await.into_response(),
+            };
+            let Some(key_id) = params.key_id else {
+                return custom_error(Error::from_string("Key not found", StatusCode::NOT_FOUND))
+                    .await
+                    .into_response();
+            };
+            let message = hex::encode(digest);
+            let options = SignOptions {
+                hash: MessageHash {
+                    function: params.hash,
+                    prehashed: true,
+                    encoding: MessageEncoding::Text,
+                },
+                encoding: params.encoding,
+                ..SignOptions::default()
+            };
+            let checked = if params.scheme.hashes_message() {
+                check_sign_options(params.scheme, &options, &message)
+            } else {
+                Err(SignServiceError::BinaryMessage(params.scheme))
+            };
+            if let Err(err) = checked {
+                return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                    .await
+                    .into_response();
+            }
+
+            let key = db.get_user_key(user_id, key_id).await;
+            let Ok((key, _)) = key else {
+                return custom_error(Error::from_string("Key not found", StatusCode::NOT_FOUND))
+                    .await
+                    .into_response();
+            };
+            if let Err(err) =
+                check_derivation_path(&key.algorithm, params.derivation_path.as_deref())
+            {
+                return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                    .await
+                    .into_response();
+            }
+
+            let mut state = state.lock().await;
+            if state.pending_messages.contains_key(&user_id) {
+                return custom_error(Error::from_string(
+                    "User already waits for message sign",
+                    StatusCode::NOT_FOUND,
+                ))
+                .await
+                .into_response();
+            }
+            state.pending_messages.insert(
+                user_id,
+                PendingMessage {
+                    key_id,
+                    derivation_path: params.derivation_path,
+                    scheme: params.scheme,
+                    options,
+                    message,
+                    file: Some(file),
+                },
+            );
+            let username = db.get_user_name(user_id).await.unwrap_or_default();
+
+            Html(format!(
+                "{}{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    HTML_BODY_CONTENT_SIGN_ONGOING
+                ),
+                HTML_SCRIPT_SSE.replace(HTML_USERID_PLACEHOLDER, &user_id.to_string()),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_sign_typed_data(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+
+            let body_content = if keys.is_empty() {
+                HTML_BODY_CONTENT_NO_KEY.replace(HTML_USERNAME_PLACEHOLDER, &username)
+            } else {
+                HTML_BODY_CONTENT_SIGN_TYPED_DATA
+                    .replace(HTML_KEY_OPTIONS_PLACEHOLDER, &key_options(&keys))
+            };
+
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_preview_typed_data(
+    Form(params): Form<TypedDataParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+            let Some(key) = keys.iter().find(|key| key.id == params.key_id) else {
+                return custom_error(Error::from_string("Key not found", StatusCode::NOT_FOUND))
+                    .await
+                    .into_response();
+            };
+            let derivation_path = derivation_path(&params.derivation_path);
+            if let Err(err) = check_derivation_path(&key.algorithm, derivation_path) {
+                return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                    .await
+                    .into_response();
+            }
+            let preview = match typed_data_preview(key, derivation_path, &params.typed_data) {
+                Ok(preview) => preview,
+                Err(err) => {
+                    return custom_error(Error::from_string(
+                        format!("Invalid EIP-712 typed data: {err}"),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                    .await
+                    .into_response()
+                }
+            };
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &preview),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_sign_psbt(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+
+            let body_content = if keys.is_empty() {
+                HTML_BODY_CONTENT_NO_KEY.replace(HTML_USERNAME_PLACEHOLDER, &username)
+            } else {
+                HTML_BODY_CONTENT_SIGN_PSBT
+                    .replace(HTML_KEY_OPTIONS_PLACEHOLDER, &key_options(&keys))
+            };
+
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_preview_psbt(
+    Form(params): Form<PsbtParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        let user_id = state.lock().await.current_users.get(&user_session).copied();
+        if let Some(user_id) = user_id {
+            let keys = db.list_user_keys(user_id).await.unwrap_or_default();
+            let Some(key) = keys.iter().find(|key| key.id == params.key_id) else {
+                return custom_error(Error::from_string("Key not found", StatusCode::NOT_FOUND))
+                    .await
+                    .into_response();
+            };
+            let derivation_path = derivation_path(&params.derivation_path);
+            if let Err(err) = check_derivation_path(&key.algorithm, derivation_path) {
+                return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                    .await
+                    .into_response();
+            }
+            let sealed_key = db.get_user_key(user_id, key.id).await;
+            let public_key = match sealed_key {
+                Ok((key, sealed_key)) => {
+                    derive_public_key(
+                        user_id,
+                        &key,
+                        &sealed_key,
+                        derivation_path,
+                        &keyring,
+                        &sign_service,
+                    )
+                    .await
+                }
+                Err(_) => None,
+            };
+            let Some(public_key) = public_key.and_then(|info| hex::decode(info.compressed).ok())
+            else {
+                return custom_error(Error::from_string(
+                    "Key can't be opened",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ))
+                .await
+                .into_response();
+            };
+            let preview = match psbt_preview(key, derivation_path, &public_key, &params.psbt) {
+                Ok(preview) => preview,
+                Err(err) => {
+                    return custom_error(Error::from_string(
+                        format!("Invalid PSBT: {err}"),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                    .await
+                    .into_response()
+                }
+            };
+
+            let username = db.get_user_name(user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &preview),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_index(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+) -> impl IntoResponse {
+    let go_to_login_view = Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, "/login")
+        .finish();
+
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+
+            let body_content = if keys.is_empty() {
+                let user_name = db
+                    .get_user_name(*user_id)
+                    .await
+                    .unwrap_or("Unknown user".to_string());
+                HTML_BODY_CONTENT_NO_KEY.replace(HTML_USERNAME_PLACEHOLDER, &user_name)
+            } else {
+                let mut public_keys = String::new();
+                for key in &keys {
+                    let sealed_key = db.get_user_key(*user_id, key.id).await;
+                    if let Ok((_, sealed_key)) = sealed_key {
+                        if let Some(public_key) = derive_public_key(
+                            *user_id,
+                            key,
+                            &sealed_key,
+                            None,
+                            &keyring,
+                            &sign_service,
+                        )
+                        .await
+                        {
+                            public_keys.push_str(&public_key_html(key, &public_key));
+                        }
+                    }
+                }
+
+                format!(
+                    "{}{}",
+                    HTML_BODY_CONTENT_SIGN_MESSAGE
+                        .replace(HTML_KEY_OPTIONS_PLACEHOLDER, &key_options(&keys))
+                        .replace(
+                            HTML_SCHEME_OPTIONS_PLACEHOLDER,
+                            &scheme_options(
+                                SignatureScheme::ALL
+                                    .iter()
+                                    .copied()
+                                    .filter(SignatureScheme::signs_text)
+                            )
+                        )
+                        .replace(HTML_ENCODING_OPTIONS_PLACEHOLDER, &encoding_options())
+                        .replace(
+                            HTML_ADDRESS_TYPE_OPTIONS_PLACEHOLDER,
+                            &address_type_options()
+                        )
+                        .replace(HTML_HASH_FIELDS_PLACEHOLDER, &hash_fields()),
+                    HTML_BODY_CONTENT_PUBLIC_KEYS
+                        .replace(HTML_PUBLIC_KEYS_PLACEHOLDER, &public_keys)
+                )
+            };
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_TYPED_DATA,
+                        HTML_NAVBAR_MENU_ITEM_SIGN_PSBT,
+                        HTML_NAVBAR_MENU_ITEM_SIGN_FILE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            // session cookit exists but user not logged in
+            session.remove(&user_session);
+            go_to_login_view
+        }
+    } else {
+        go_to_login_view
+    }
+}
+
+#[handler]
+async fn view_logout(session: &Session, state: Data<&Arc<Mutex<WebApp>>>) -> impl IntoResponse {
+    state.lock().await.end_session(session);
+
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, "/")
+        .finish()
+}
+
+#[handler]
+async fn view_keys(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+    Data(key_export): Data<&KeyExportPolicy>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let keys = db.list_user_keys(*user_id).await.unwrap_or_default();
+            let mut derived_addresses = String::new();
+            for key in keys
+                .iter()
+                .filter(|key| key.algorithm == KEY_ALGORITHM_BIP32)
+            {
+                let sealed_key = db.get_user_key(*user_id, key.id).await;
+                if let Ok((_, sealed_key)) = sealed_key {
+                    let addresses =
+                        derive_addresses(*user_id, key, &sealed_key, &keyring, &sign_service).await;
+                    derived_addresses.push_str(&derived_addresses_html(key, &addresses));
+                }
+            }
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_TOKENS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_KEY_LIST
+                        .replace(HTML_KEY_ROWS_PLACEHOLDER, &key_rows(&keys, *key_export))
+                        .replace(HTML_DERIVED_ADDRESSES_PLACEHOLDER, &derived_addresses)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_generate_key(
+    Form(params): Form<GenerateKeyParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let label = params.label.trim();
+            let exportable = params.non_exportable.is_none();
+            if let Err(err) =
+                generate_user_key(*user_id, label, exportable, &db, &keyring, &sign_service).await
+            {
+                return custom_error(err).await.into_response();
+            }
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_KEY_GENERATED.replace(HTML_KEY_LABEL_PLACEHOLDER, label)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_import_key(
+    Form(params): Form<ImportKeyParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let label = params.label.trim();
+            let password = params
+                .password
+                .as_deref()
+                .filter(|password| !password.is_empty());
+            let import = KeyImport {
+                label,
+                key: &params.key,
+                password,
+                exportable: params.non_exportable.is_none(),
+            };
+            if let Err(err) = import_user_key(*user_id, import, &db, &keyring).await {
+                return custom_error(err).await.into_response();
+            }
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_KEY_IMPORTED.replace(HTML_KEY_LABEL_PLACEHOLDER, label)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_add_hd_key(
+    Form(params): Form<HdKeyParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let label = params.label.trim();
+            let mnemonic = params
+                .mnemonic
+                .as_deref()
+                .filter(|mnemonic| !mnemonic.trim().is_empty());
+            let generated = match add_hd_user_key(
+                *user_id,
+                label,
+                mnemonic,
+                &params.passphrase,
+                &db,
+                &keyring,
+                &sign_service,
+            )
+            .await
+            {
+                Ok((_, generated)) => generated,
+                Err(err) => return custom_error(err).await.into_response(),
+            };
+
+            let body_content = match generated {
+                Some(mnemonic) => HTML_BODY_CONTENT_HD_KEY_GENERATED
+                    .replace(HTML_KEY_LABEL_PLACEHOLDER, label)
+                    .replace(HTML_MNEMONIC_PLACEHOLDER, &mnemonic),
+                None => {
+                    HTML_BODY_CONTENT_HD_KEY_IMPORTED.replace(HTML_KEY_LABEL_PLACEHOLDER, label)
+                }
+            };
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_export_key(
+    Path(key_id): Path<KeyId>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    Data(key_export): Data<&KeyExportPolicy>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let key = db.get_user_key(*user_id, key_id).await;
+            let key = match key {
+                Ok((key, _)) => key,
+                Err(_) => {
+                    return custom_error(Error::from_string(
+                        "User doesn't have such key",
+                        StatusCode::NOT_FOUND,
+                    ))
+                    .await
+                    .into_response()
+                }
+            };
+            if let Err(err) = check_key_export(*key_export, &key) {
+                return custom_error(err).await.into_response();
+            }
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_KEY_EXPORT
+                        .replace(HTML_KEY_ID_PLACEHOLDER, &key.id.to_string())
+                        .replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+#[allow(clippy::too_many_arguments)]
+async fn view_export_key_download(
+    Path(key_id): Path<KeyId>,
+    Form(params): Form<ExportKeyParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    passwords: Data<&Arc<PasswordHashing>>,
+    Data(key_export): Data<&KeyExportPolicy>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        let user_id = state.lock().await.current_users.get(&user_session).copied();
+        if let Some(user_id) = user_id {
+            if params.keystore_password != params.keystore_password_confirm {
+                return custom_error(Error::from_string(
+                    "Keystore passwords don't match",
+                    StatusCode::BAD_REQUEST,
+                ))
+                .await
+                .into_response();
+            }
+            let kdf = match params.kdf.parse::<Kdf>() {
+                Ok(kdf) => kdf,
+                Err(err) => {
+                    return custom_error(Error::from_string(err, StatusCode::BAD_REQUEST))
+                        .await
+                        .into_response()
+                }
+            };
+
+            let export = KeyExport {
+                key_id,
+                password: &params.password,
+                keystore_password: &params.keystore_password,
+                kdf,
+            };
+            match export_user_key(user_id, export, *key_export, &db, &keyring, &passwords).await {
+                Ok((key, keystore)) => Response::builder()
+                    .content_type("application/json")
+                    .header(
+                        header::CONTENT_DISPOSITION,
+                        format!(
+                            "attachment; filename=\"{}.keystore.json\"",
+                            key.label.replace(' ', "_")
+                        ),
+                    )
+                    .body(keystore),
+                Err(err) => custom_error(err).await.into_response(),
+            }
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_lock_key_export(
+    Path(key_id): Path<KeyId>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            let locked = db.set_key_non_exportable(*user_id, key_id).await;
+            let key = db.get_user_key(*user_id, key_id).await;
+            let key = match locked.and(key) {
+                Ok((key, _)) => key,
+                Err(_) => {
+                    return custom_error(Error::from_string(
+                        "User doesn't have such key",
+                        StatusCode::NOT_FOUND,
+                    ))
+                    .await
+                    .into_response()
+                }
+            };
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}{}",
+                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+                        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                        HTML_NAVBAR_MENU_ITEM_KEYS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_KEY_LOCKED.replace(HTML_KEY_LABEL_PLACEHOLDER, &key.label)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
+        } else {
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
+        }
+    } else {
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
+    }
+}
+
+#[handler]
+async fn view_discard_key(
+    Path(key_id): Path<KeyId>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    if let Some(user_session) = session.get::<String>("user_session") {
+        if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
+            if db.discard_user_key(*user_id, key_id).await.is_err() {
+                custom_error(Error::from_string(
+                    "User doesn't have such key",
+                    StatusCode::NOT_FOUND,
+                ))
+                .await
+                .into_response()
+            } else {
+                let username = db.get_user_name(*user_id).await.unwrap_or_default();
+                Html(format!(
+                    "{}{}{}{}",
+                    HTML_HEAD,
+                    HTML_BODY_NAVBAR.replace(
+                        HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                        &format!(
+                            "{}{}{}",
+                            HTML_NAVBAR_MENU_ITEM_LOGOUT
+                                .replace(HTML_USERNAME_PLACEHOLDER, &username),
+                            HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+                            HTML_NAVBAR_MENU_ITEM_KEYS
                         )
                     ),
                     HTML_BODY_CONTENT.replace(
                         HTML_BODY_CONTENT_PLACEHOLDER,
-                        &HTML_BODY_CONTENT_MESSAGE_SIGNED
-                            .replace(HTML_BODY_CONTENT_INTERNAL_PLACEHOLDER, &msg)
+                        HTML_BODY_CONTENT_KEY_DISCARDED
                     ),
                     HTML_BODY_FOOTER
                 ))
                 .into_response()
-            } else {
-                custom_error(Error::from_string(
-                    "User doesn't have any signed messages",
-                    StatusCode::NOT_FOUND,
-                ))
-                .await
-                .into_response()
             }
         } else {
             custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
@@ -217,118 +2305,153 @@ async fn view_message_signed(
     }
 }
 
+fn token_rows(tokens: &[ApiTokenInfo]) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            HTML_TOKEN_ROW
+                .replace(HTML_TOKEN_ID_PLACEHOLDER, &token.id.to_string())
+                .replace(HTML_TOKEN_NAME_PLACEHOLDER, &token.name)
+                .replace(HTML_TOKEN_SCOPES_PLACEHOLDER, &token.scopes.join(" "))
+                .replace(
+                    HTML_TOKEN_CREATED_PLACEHOLDER,
+                    &format_timestamp(token.created_at),
+                )
+                .replace(
+                    HTML_TOKEN_LAST_USED_PLACEHOLDER,
+                    &token
+                        .last_used_at
+                        .map(format_timestamp)
+                        .unwrap_or_else(|| "never".to_string()),
+                )
+        })
+        .collect()
+}
+
+/// Creates an API token for the user, returns the token which is shown only once.
+async fn create_api_token(
+    user_id: UserId,
+    name: &str,
+    scopes: &str,
+    db: &SharedStorage,
+) -> poem::Result<String> {
+    if !is_valid_label(name) {
+        return Err(Error::from_string(
+            "Token name must have up to 32 letters, digits, spaces, '-', '_' or '.'",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let scopes =
+        parse_scopes(scopes).map_err(|err| Error::from_string(err, StatusCode::BAD_REQUEST))?;
+
+    let keys = db
+        .list_user_keys(user_id)
+        .await
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?;
+    for scope in &scopes {
+        if let Scope::Sign(Some(key_id)) = scope {
+            if !keys.iter().any(|key| key.id == *key_id) {
+                return Err(Error::from_string(
+                    format!("User doesn't have key {key_id}"),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+        }
+    }
+
+    let token = generate_token();
+    let scopes: Vec<String> = scopes.iter().map(Scope::to_string).collect();
+    db.add_api_token(user_id, name, &hash_token(&token), &scopes)
+        .await
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(token)
+}
+
+fn user_menu_items(username: &str) -> String {
+    format!(
+        "{}{}{}",
+        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, username),
+        HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+        HTML_NAVBAR_MENU_ITEM_KEYS
+    )
+}
+
 #[handler]
-async fn view_index(
+async fn view_tokens(
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
 ) -> impl IntoResponse {
-    let go_to_login_view = Response::builder()
-        .status(StatusCode::FOUND)
-        .header(header::LOCATION, "/login")
-        .finish();
-
     if let Some(user_session) = session.get::<String>("user_session") {
         if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
-            let key_available = db.lock().await.get_user_key(*user_id).is_ok();
-
-            let (menu_item, body_content) = if !key_available {
-                let user_name = db
-                    .lock()
-                    .await
-                    .get_user_name(*user_id)
-                    .unwrap_or("Unknown user".to_string());
-                (
-                    HTML_NAVBAR_MENU_ITEM_GENERATE_KEY,
-                    HTML_BODY_CONTENT_NO_KEY.replace(HTML_USERNAME_PLACEHOLDER, &user_name),
-                )
-            } else {
-                (
-                    HTML_NAVBAR_MENU_ITEM_DISCARD_KEY,
-                    HTML_BODY_CONTENT_SIGN_MESSAGE.to_string(),
-                )
-            };
-            let username = db.lock().await.get_user_name(*user_id).unwrap_or_default();
-
+            let tokens = db.list_api_tokens(*user_id).await.unwrap_or_default();
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
             Html(format!(
                 "{}{}{}{}",
                 HTML_HEAD,
                 HTML_BODY_NAVBAR.replace(
                     HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
-                    &format!(
-                        "{}{}",
-                        HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
-                        menu_item
-                    )
+                    &user_menu_items(&username)
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_TOKEN_LIST
+                        .replace(HTML_TOKEN_ROWS_PLACEHOLDER, &token_rows(&tokens))
                 ),
-                HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
                 HTML_BODY_FOOTER
             ))
             .into_response()
         } else {
-            // session cookit exists but user not logged in
-            session.remove(&user_session);
-            go_to_login_view
+            custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
+                .await
+                .into_response()
         }
     } else {
-        go_to_login_view
-    }
-}
-
-#[handler]
-async fn view_logout(session: &Session, state: Data<&Arc<Mutex<WebApp>>>) -> impl IntoResponse {
-    if let Some(user_session) = session.get::<String>("user_session") {
-        state.lock().await.current_users.remove(&user_session);
+        custom_error(Error::from_string(
+            "User session not found",
+            StatusCode::NOT_FOUND,
+        ))
+        .await
+        .into_response()
     }
-
-    session.purge();
-    Response::builder()
-        .status(StatusCode::FOUND)
-        .header(header::LOCATION, "/")
-        .finish()
 }
 
 #[handler]
-async fn view_generate_key(
+async fn view_create_token(
+    Form(params): Form<CreateTokenParams>,
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
-    sign_service: Data<&Arc<Mutex<SignService>>>,
+    db: Data<&SharedStorage>,
 ) -> impl IntoResponse {
     if let Some(user_session) = session.get::<String>("user_session") {
         if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
-            if db.lock().await.get_user_key(*user_id).is_ok() {
-                custom_error(Error::from_string(
-                    "User already has a key",
-                    StatusCode::NOT_FOUND,
-                ))
-                .await
-                .into_response()
-            } else {
-                let key = sign_service.lock().await.generate_key();
-                db.lock().await.add_user_key(*user_id, &key).ok();
-                let username = db.lock().await.get_user_name(*user_id).unwrap_or_default();
-                Html(format!(
-                    "{}{}{}{}",
-                    HTML_HEAD,
-                    HTML_BODY_NAVBAR.replace(
-                        HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
-                        &format!(
-                            "{}{}{}",
-                            HTML_NAVBAR_MENU_ITEM_LOGOUT
-                                .replace(HTML_USERNAME_PLACEHOLDER, &username),
-                            HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
-                            HTML_NAVBAR_MENU_ITEM_DISCARD_KEY
-                        )
-                    ),
-                    HTML_BODY_CONTENT.replace(
-                        HTML_BODY_CONTENT_PLACEHOLDER,
-                        HTML_BODY_CONTENT_KEY_GENERATED
-                    ),
-                    HTML_BODY_FOOTER
-                ))
-                .into_response()
-            }
+            let name = params.name.trim();
+            let token = match create_api_token(*user_id, name, &params.scopes, &db).await {
+                Ok(token) => token,
+                Err(err) => return custom_error(err).await.into_response(),
+            };
+
+            let username = db.get_user_name(*user_id).await.unwrap_or_default();
+            Html(format!(
+                "{}{}{}{}",
+                HTML_HEAD,
+                HTML_BODY_NAVBAR.replace(
+                    HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+                    &format!(
+                        "{}{}",
+                        user_menu_items(&username),
+                        HTML_NAVBAR_MENU_ITEM_TOKENS
+                    )
+                ),
+                HTML_BODY_CONTENT.replace(
+                    HTML_BODY_CONTENT_PLACEHOLDER,
+                    &HTML_BODY_CONTENT_TOKEN_CREATED
+                        .replace(HTML_TOKEN_NAME_PLACEHOLDER, name)
+                        .replace(HTML_TOKEN_PLACEHOLDER, &token)
+                ),
+                HTML_BODY_FOOTER
+            ))
+            .into_response()
         } else {
             custom_error(Error::from_string("User not found", StatusCode::NOT_FOUND))
                 .await
@@ -345,23 +2468,23 @@ async fn view_generate_key(
 }
 
 #[handler]
-async fn view_discard_key(
+async fn view_revoke_token(
+    Path(token_id): Path<TokenId>,
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
 ) -> impl IntoResponse {
     if let Some(user_session) = session.get::<String>("user_session") {
         if let Some(user_id) = state.lock().await.current_users.get(&user_session) {
-            if db.lock().await.get_user_key(*user_id).is_err() {
+            if db.revoke_api_token(*user_id, token_id).await.is_err() {
                 custom_error(Error::from_string(
-                    "User doesn't have a key",
+                    "User doesn't have such token",
                     StatusCode::NOT_FOUND,
                 ))
                 .await
                 .into_response()
             } else {
-                db.lock().await.discard_user_key(*user_id).ok();
-                let username = db.lock().await.get_user_name(*user_id).unwrap_or_default();
+                let username = db.get_user_name(*user_id).await.unwrap_or_default();
                 Html(format!(
                     "{}{}{}{}",
                     HTML_HEAD,
@@ -369,14 +2492,13 @@ async fn view_discard_key(
                         HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
                         &format!(
                             "{}{}",
-                            HTML_NAVBAR_MENU_ITEM_LOGOUT
-                                .replace(HTML_USERNAME_PLACEHOLDER, &username),
-                            HTML_NAVBAR_MENU_ITEM_GENERATE_KEY
+                            user_menu_items(&username),
+                            HTML_NAVBAR_MENU_ITEM_TOKENS
                         )
                     ),
                     HTML_BODY_CONTENT.replace(
                         HTML_BODY_CONTENT_PLACEHOLDER,
-                        HTML_BODY_CONTENT_KEY_DISCARDED
+                        HTML_BODY_CONTENT_TOKEN_REVOKED
                     ),
                     HTML_BODY_FOOTER
                 ))
@@ -397,17 +2519,319 @@ async fn view_discard_key(
     }
 }
 
+/// Menu items of public pages, depending on whether the user is logged in.
+async fn public_menu_items(
+    session: &Session,
+    state: &Arc<Mutex<WebApp>>,
+    db: &SharedStorage,
+) -> String {
+    let user_id = state.lock().await.session_user(session);
+
+    if let Some(user_id) = user_id {
+        let username = db.get_user_name(user_id).await.unwrap_or_default();
+        format!(
+            "{}{}{}",
+            HTML_NAVBAR_MENU_ITEM_LOGOUT.replace(HTML_USERNAME_PLACEHOLDER, &username),
+            HTML_NAVBAR_MENU_ITEM_SIGN_MESSAGE,
+            HTML_NAVBAR_MENU_ITEM_KEYS
+        )
+    } else {
+        HTML_NAVBAR_MENU_ITEM_LOGIN.to_string()
+    }
+}
+
+#[handler]
+async fn view_verify(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &public_menu_items(session, &state, &db).await
+        ),
+        HTML_BODY_CONTENT.replace(
+            HTML_BODY_CONTENT_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                HTML_BODY_CONTENT_VERIFY
+                    .replace(
+                        HTML_SCHEME_OPTIONS_PLACEHOLDER,
+                        &scheme_options(SignatureScheme::ALL.iter().copied()),
+                    )
+                    .replace(HTML_HASH_FIELDS_PLACEHOLDER, &hash_fields()),
+                HTML_BODY_CONTENT_VERIFY_BUNDLE
+            )
+        ),
+        HTML_BODY_FOOTER
+    ))
+}
+
+#[handler]
+async fn view_verify_result(
+    Form(params): Form<VerifyParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    let body_content = match SignService::verify_message(
+        params.scheme,
+        MessageHash {
+            function: params.hash,
+            prehashed: params.prehashed,
+            encoding: params.message_encoding,
+        },
+        &params.message,
+        &params.signature,
+        &params.public_key,
+    ) {
+        Ok(()) => HTML_BODY_CONTENT_SIGNATURE_VALID.to_string(),
+        Err(err) => HTML_BODY_CONTENT_SIGNATURE_INVALID
+            .replace(HTML_ERROR_PLACEHOLDER, &escape_html(&err.to_string())),
+    };
+
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                public_menu_items(session, &state, &db).await,
+                HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+        HTML_BODY_FOOTER
+    ))
+}
+
+#[handler]
+async fn view_verify_bundle_result(
+    Form(params): Form<VerifyBundleParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    let message = Some(params.message.as_str()).filter(|message| !message.is_empty());
+    let res = match serde_json::from_str::<SignatureBundle>(&params.bundle) {
+        Ok(bundle) => SignService::verify_bundle(&bundle, message, false, params.message_encoding)
+            .map(|()| bundle)
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(format!("Invalid signature bundle: {err}")),
+    };
+    let body_content = match res {
+        Ok(bundle) => HTML_BODY_CONTENT_BUNDLE_VALID
+            .replace(
+                HTML_BUNDLE_SUBJECT_PLACEHOLDER,
+                match (message, &bundle.filename) {
+                    (Some(_), _) => "message",
+                    (None, Some(_)) => "digest of the file",
+                    (None, None) => "digest",
+                },
+            )
+            .replace(
+                HTML_PUBLIC_KEY_COMPRESSED_PLACEHOLDER,
+                &escape_html(&bundle.public_key),
+            )
+            .replace(HTML_SCHEME_TITLE_PLACEHOLDER, bundle.algorithm.title())
+            .replace(HTML_USERNAME_PLACEHOLDER, &escape_html(&bundle.signer))
+            .replace(HTML_KEY_ID_PLACEHOLDER, &bundle.key_id.to_string())
+            .replace(
+                HTML_TIMESTAMP_PLACEHOLDER,
+                &format_timestamp(bundle.timestamp),
+            ),
+        Err(err) => {
+            HTML_BODY_CONTENT_SIGNATURE_INVALID.replace(HTML_ERROR_PLACEHOLDER, &escape_html(&err))
+        }
+    };
+
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                public_menu_items(session, &state, &db).await,
+                HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(HTML_BODY_CONTENT_PLACEHOLDER, &body_content),
+        HTML_BODY_FOOTER
+    ))
+}
+
+#[handler]
+async fn verify_json(Json(params): Json<VerifyParams>) -> Json<VerifyResult> {
+    let res = SignService::verify_message(
+        params.scheme,
+        MessageHash {
+            function: params.hash,
+            prehashed: params.prehashed,
+            encoding: params.message_encoding,
+        },
+        &params.message,
+        &params.signature,
+        &params.public_key,
+    );
+
+    Json(VerifyResult {
+        valid: res.is_ok(),
+        reason: res.err().map(|err| err.to_string()),
+    })
+}
+
+#[handler]
+async fn view_recover(
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                public_menu_items(session, &state, &db).await,
+                HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(
+            HTML_BODY_CONTENT_PLACEHOLDER,
+            &HTML_BODY_CONTENT_RECOVER
+                .replace(
+                    HTML_SCHEME_OPTIONS_PLACEHOLDER,
+                    &scheme_options(
+                        SignatureScheme::ALL
+                            .iter()
+                            .copied()
+                            .filter(SignatureScheme::is_recoverable)
+                    ),
+                )
+                .replace(HTML_HASH_FIELDS_PLACEHOLDER, &hash_fields())
+        ),
+        HTML_BODY_FOOTER
+    ))
+}
+
+#[handler]
+async fn view_recover_result(
+    Form(params): Form<RecoverParams>,
+    session: &Session,
+    state: Data<&Arc<Mutex<WebApp>>>,
+    db: Data<&SharedStorage>,
+) -> impl IntoResponse {
+    let public_key = SignService::recover_public_key(
+        params.scheme,
+        MessageHash {
+            function: params.hash,
+            prehashed: params.prehashed,
+            encoding: params.message_encoding,
+        },
+        &params.message,
+        &params.signature,
+    );
+    let public_key = match public_key {
+        Ok(public_key) => public_key,
+        Err(err) => {
+            return custom_error(Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))
+                .await
+                .into_response()
+        }
+    };
+
+    Html(format!(
+        "{}{}{}{}",
+        HTML_HEAD,
+        HTML_BODY_NAVBAR.replace(
+            HTML_NAVBAR_MENU_ITEM_PLACEHOLDER,
+            &format!(
+                "{}{}",
+                public_menu_items(session, &state, &db).await,
+                HTML_NAVBAR_MENU_ITEM_VERIFY
+            )
+        ),
+        HTML_BODY_CONTENT.replace(
+            HTML_BODY_CONTENT_PLACEHOLDER,
+            &HTML_BODY_CONTENT_RECOVERED.replace(
+                HTML_PUBLIC_KEY_TABLE_PLACEHOLDER,
+                &public_key_table(&public_key)
+            )
+        ),
+        HTML_BODY_FOOTER
+    ))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct PublicKeyParams {
+    derivation_path: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PublicKeyResponse {
+    key_id: KeyId,
+    public_id: String,
+    algorithm: String,
+    #[serde(flatten)]
+    public_key: PublicKeyInfo,
+}
+
+#[handler]
+async fn public_key_json(
+    Path(public_id): Path<String>,
+    Query(params): Query<PublicKeyParams>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
+    sign_service: Data<&Arc<Mutex<SignService>>>,
+) -> poem::Result<Json<PublicKeyResponse>> {
+    let (user_id, key_info, sealed_key) = db
+        .get_key_by_public_id(&public_id)
+        .await
+        .map_err(|_| Error::from_string("Key not found", StatusCode::NOT_FOUND))?;
+
+    let derivation_path = derivation_path(&params.derivation_path);
+    check_derivation_path(&key_info.algorithm, derivation_path)
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+    let public_key = derive_public_key(
+        user_id,
+        &key_info,
+        &sealed_key,
+        derivation_path,
+        &keyring,
+        &sign_service,
+    )
+    .await
+    .ok_or_else(|| Error::from_string("Key is not available", StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(Json(PublicKeyResponse {
+        key_id: key_info.id,
+        public_id,
+        algorithm: key_info.algorithm,
+        public_key,
+    }))
+}
+
 pub async fn custom_error(err: Error) -> impl IntoResponse {
+    let status = err.status();
     Html(format!(
         "{}{}{}{}",
         HTML_HEAD,
         HTML_BODY_NAVBAR.replace(HTML_NAVBAR_MENU_ITEM_PLACEHOLDER, ""),
         HTML_BODY_CONTENT.replace(
             HTML_BODY_CONTENT_PLACEHOLDER,
-            &HTML_BODY_CONTENT_ANY_ERROR.replace(HTML_ERROR_PLACEHOLDER, &err.to_string())
+            &HTML_BODY_CONTENT_ANY_ERROR
+                .replace(HTML_ERROR_PLACEHOLDER, &escape_html(&err.to_string()))
         ),
         HTML_BODY_FOOTER
     ))
+    .with_status(status)
     .into_response()
 }
 
@@ -429,7 +2853,8 @@ async fn event(
     Path(user_id): Path<UserId>,
     session: &Session,
     state: Data<&Arc<Mutex<WebApp>>>,
-    db: Data<&Arc<Mutex<MemDb>>>,
+    db: Data<&SharedStorage>,
+    keyring: Data<&Arc<Keyring>>,
     sign_service: Data<&Arc<Mutex<SignService>>>,
 ) -> SSE {
     println!("1");
@@ -441,11 +2866,59 @@ async fn event(
             if user_id == *user_id_from_state {
                 println!("1");
 
-                if let Some(msg) = state.pending_messages.remove(&user_id) {
-                    if let Ok(key) = db.lock().await.get_user_key(user_id) {
-                        if let Ok(output) = sign_service.lock().await.sign_message(&msg, &key).await
-                        {
-                            state.signed_messages.insert(user_id, output);
+                if let Some(pending) = state.pending_messages.remove(&user_id) {
+                    let key = db
+                        .get_user_key(user_id, pending.key_id)
+                        .await
+                        .ok()
+                        .and_then(|(key, sealed_key)| {
+                            let secret = keyring.open(user_id, &key, &sealed_key).ok()?;
+                            Some((key, secret))
+                        });
+                    if let Some((key, secret)) = key {
+                        let sign_service = sign_service.lock().await;
+                        let output = match sign_service.child_key(
+                            &key.algorithm,
+                            &secret,
+                            pending.derivation_path.as_deref(),
+                        ) {
+                            Ok(child_key) => sign_service
+                                .sign_message(
+                                    pending.scheme,
+                                    pending.options,
+                                    &pending.message,
+                                    &child_key,
+                                )
+                                .await
+                                .and_then(|output| {
+                                    Ok((output, sign_service.public_key(&child_key)?))
+                                }),
+                            Err(err) => Err(err),
+                        };
+                        if let Ok((output, public_key)) = output {
+                            let username = db.get_user_name(user_id).await.unwrap_or_default();
+                            let mut bundle = SignatureBundle::new(
+                                pending.scheme,
+                                &pending.options,
+                                output,
+                                &public_key,
+                                pending.key_id,
+                                username,
+                            )
+                            .with_derivation_path(pending.derivation_path.as_deref());
+                            if let Some(file) = pending.file {
+                                bundle = bundle.with_file(file.filename, file.size);
+                            }
+                            state.signed_messages.insert(
+                                user_id,
+                                SignedMessage {
+                                    key_id: pending.key_id,
+                                    derivation_path: pending.derivation_path,
+                                    scheme: pending.scheme,
+                                    options: pending.options,
+                                    bundle,
+                                },
+                            );
                             Event::message(format!(
                                 r##"{{"user_id": {user_id}, "error": "none"}}"##
                             ))
@@ -488,14 +2961,28 @@ impl WebApp {
         Self::default()
     }
 
-    fn hash_password(pass: &str) -> Option<String> {
-        let setup = BcryptSetup {
-            salt: Some("gifLHpZdNAixJzy36HyOcK"),
-            cost: Some(5),
-            variant: Some(BcryptVariant::V2y),
-        };
+    /// Binds the cookie session to the logged in user.
+    pub(crate) fn start_session(&mut self, session: &Session, user_id: UserId) {
+        let user_session: String = (0..16)
+            .map(|_| char::from(rand::thread_rng().gen_range(32..127)))
+            .collect();
+        session.set("user_session", &user_session);
+
+        self.current_users.insert(user_session, user_id);
+    }
+
+    pub(crate) fn session_user(&self, session: &Session) -> Option<UserId> {
+        session
+            .get::<String>("user_session")
+            .and_then(|user_session| self.current_users.get(&user_session).copied())
+    }
+
+    pub(crate) fn end_session(&mut self, session: &Session) {
+        if let Some(user_session) = session.get::<String>("user_session") {
+            self.current_users.remove(&user_session);
+        }
 
-        hash_with(setup, pass).ok()
+        session.purge();
     }
 
     pub fn setup_route() -> Route {
@@ -503,11 +2990,93 @@ impl WebApp {
             .at("/", get(view_index))
             .at("/login", get(view_login).post(view_login_validate))
             .at("/logout", get(view_logout))
+            .at("/register", get(view_register).post(view_register_submit))
             .at("/sign", post(view_sign_message))
+            .at(
+                "/sign/typed-data",
+                get(view_sign_typed_data).post(view_preview_typed_data),
+            )
+            .at("/sign/psbt", get(view_sign_psbt).post(view_preview_psbt))
+            .at(
+                "/sign/file",
+                get(view_sign_file).post(view_sign_file_upload),
+            )
             .at("/event/:user_id", get(event))
             .at("/message-signed", get(view_message_signed))
-            .at("/key/generate", get(view_generate_key))
-            .at("/key/discard", get(view_discard_key))
+            .at("/keys", get(view_keys))
+            .at("/keys/:public_id/public", get(public_key_json))
+            .at("/key/generate", post(view_generate_key))
+            .at("/key/import", post(view_import_key))
+            .at("/key/hd", post(view_add_hd_key))
+            .at(
+                "/key/export/:key_id",
+                get(view_export_key).post(view_export_key_download),
+            )
+            .at("/key/non-exportable/:key_id", post(view_lock_key_export))
+            .at("/key/discard/:key_id", post(view_discard_key))
+            .at("/settings/tokens", get(view_tokens).post(view_create_token))
+            .at("/settings/tokens/revoke/:token_id", post(view_revoke_token))
+            .at("/verify", get(view_verify).post(view_verify_result))
+            .at("/verify/bundle", post(view_verify_bundle_result))
+            .at("/verify/json", post(verify_json))
+            .at("/recover", get(view_recover).post(view_recover_result))
             .at("/favicon.ico", get(favicon))
+            .nest("/api", api::setup_route())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::{FromRequest, Request, RequestBody};
+
+    async fn sign_file_form(
+        fields: &[(&str, &str)],
+    ) -> Result<(SignFileParams, SignedFile, [u8; 32]), Error> {
+        let mut body = String::new();
+        for (name, value) in fields {
+            let filename = if *name == "file" {
+                "; filename=\"a.txt\""
+            } else {
+                ""
+            };
+            body += &format!(
+                "--X\r\nContent-Disposition: form-data; name=\"{name}\"{filename}\r\n\r\n{value}\r\n"
+            );
+        }
+        body += "--X--\r\n";
+        let req = Request::builder()
+            .header("content-type", "multipart/form-data; boundary=X")
+            .finish();
+        let multipart = Multipart::from_request(&req, &mut RequestBody::new(body.into()))
+            .await
+            .unwrap();
+        read_sign_file_form(multipart).await
+    }
+
+    #[tokio::test]
+    async fn sign_file_form_order() {
+        let (params, file, digest) =
+            sign_file_form(&[("key_id", "3"), ("hash", "sha256d"), ("file", "abc")])
+                .await
+                .unwrap();
+        assert_eq!(params.key_id, Some(3));
+        assert_eq!(params.hash, HashFunction::DoubleSha256);
+        assert_eq!((file.filename.as_str(), file.size), ("a.txt", 3));
+        assert_eq!(
+            hex::encode(digest),
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+
+        for fields in [
+            &[("file", "abc"), ("key_id", "3")][..],
+            &[("key_id", "3"), ("file", "abc"), ("hash", "sha256d")],
+        ] {
+            let err = sign_file_form(fields).await.err().unwrap();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(err.to_string(), "file must be the last field");
+        }
+        let err = sign_file_form(&[("key_id", "3")]).await.err().unwrap();
+        assert_eq!(err.to_string(), "No file was uploaded");
     }
 }